@@ -5,8 +5,6 @@ if [[ -n ${BD_LOADED-} ]]; then
 fi
 BD_LOADED=1
 
-BD_MAX_BACK=999
-
 _bd_default_core_bin() {
   local bin
   bin=$(command -v bd-core 2>/dev/null)
@@ -44,6 +42,16 @@ _bd_compute_session_id() {
 
 BD_SESSION_ID=${BD_SESSION_ID:-$(_bd_compute_session_id)}
 
+# A shell started from one that loaded bd (a subshell, or a tmux pane) begins with that
+# shell's recent history; BD_PARENT_SESSION_ID passes the session on to child shells.
+_bd_parent_session_id=${BD_PARENT_SESSION_ID-}
+export BD_PARENT_SESSION_ID=$BD_SESSION_ID
+# tmux starts panes with the server's environment rather than the split pane's; this
+# pane option lets `split-window -e BD_PARENT_SESSION_ID=#{@bd_session}` pass it instead.
+if [[ -n ${TMUX-} ]]; then
+  tmux set-option -pq @bd_session "$BD_SESSION_ID" 2>/dev/null
+fi
+
 _bd_require_core() {
   if [[ -x $BD_CORE_BIN ]]; then
     return 0
@@ -57,7 +65,26 @@ _bd_require_core() {
 
 _bd_record() {
   _bd_require_core || return 1
-  "$BD_CORE_BIN" record --session "$BD_SESSION_ID" --pwd "$PWD"
+  "$BD_CORE_BIN" record --session "$BD_SESSION_ID" --pwd "$PWD" ${BD_PATH_POLICY:+--path-policy=$BD_PATH_POLICY} "$@"
+}
+
+# Records $PWD from a hook, where output would clutter the prompt. Failures other than
+# a directory that vanished (exit 5), such as an invalid config, still get a warning.
+# bd-core runs without a command substitution, so the hook costs no extra fork.
+_bd_record_hook() {
+  local rc reason
+  _bd_require_core >/dev/null || return 1
+  "$BD_CORE_BIN" record --session "$BD_SESSION_ID" --pwd "$PWD" ${BD_PATH_POLICY:+--path-policy=$BD_PATH_POLICY} "$@" >/dev/null 2>&1
+  rc=$?
+  case $rc in
+    0|5) return "$rc" ;;
+    7) reason="database is corrupt; run bd doctor" ;;
+    8) reason="database is from a newer bd-core" ;;
+    9) reason="invalid config; run bd-core config validate" ;;
+    *) reason="bd-core record exited with $rc" ;;
+  esac
+  printf '%s\n' "bd: directory not recorded: $reason" >&2
+  return "$rc"
 }
 
 back_directory_prompt() {
@@ -68,7 +95,7 @@ back_directory_prompt() {
   fi
   if [[ $PWD != "$BD_LAST_PWD" ]]; then
     BD_LAST_PWD=$PWD
-    _bd_record >/dev/null 2>&1
+    _bd_record_hook
   fi
 }
 
@@ -94,6 +121,32 @@ _bd_add_prompt_command() {
 }
 
 bd() {
+  local scope="${BD_SCOPE:-session}"
+  local unique=
+  while (( $# > 0 )); do
+    case $1 in
+      --unique)
+        unique=1
+        shift
+        ;;
+      --scope)
+        if (( $# < 2 )); then
+          printf '%s\n' "bd: usage: bd --scope session|host|global [N|f|g|p|ls]"
+          return 2
+        fi
+        scope=$2
+        shift 2
+        ;;
+      --scope=*)
+        scope=${1#--scope=}
+        shift
+        ;;
+      *)
+        break
+        ;;
+    esac
+  done
+
   local arg="${1-}"
 
   if [[ -z $arg ]]; then
@@ -102,13 +155,30 @@ bd() {
 
   if [[ $arg == "h" || $arg == "help" || $arg == "-h" || $arg == "--help" ]]; then
     cat <<'EOF'
-usage: bd [N|c|ls|doctor|optimize|vacuum|h]
+usage: bd [N|@TIME|c|ls|p|f|g|u|q|m|pause|resume|forget|prune|sessions|doctor|optimize|vacuum|h]
 
 Commands:
   bd                 go back 1 directory
-  bd N               go back N directories (1 <= N <= 999)
+  bd N               go back N directories (1 <= N <= max_back, default 999)
+  bd @TIME           go back to where this session was TIME ago (e.g. @15m, @2h, @1d)
   bd c               cancel the last bd command
-  bd ls [N]          list recent targets with their N values (default 10)
+  bd ls [N]          list recent targets with their N values (default: default_list, 10)
+  bd p               pick a recent target interactively (type to filter)
+  bd f [N]           go forward N directories after going back (default 1)
+  bd f ls [N]        list forward targets with their N values (default: default_list, 10)
+  bd g PATTERN       go back to the most recent directory matching PATTERN
+  bd u [NAME|N]      go up to the nearest ancestor named NAME, or N levels (default 1)
+  bd q TERMS...      go to the best frecency match across all history
+  bd q ls [TERMS...] list frecency matches with their scores
+  bd m NAME          go to the bookmarked directory NAME
+  bd m set NAME      bookmark the current directory as NAME
+  bd m rm NAME       remove the bookmark NAME
+  bd m [ls]          list bookmarks
+  bd pause           stop recording directories in this session (history stays usable)
+  bd resume          record directories again
+  bd forget PATH     delete PATH from this session's history
+  bd prune           delete this session's entries for directories that no longer exist
+  bd sessions        list sessions, marking this one with *
   bd doctor [opts]   show database status
   bd optimize        rebuild SQLite DB to reclaim space (can be slow)
   bd vacuum          reset SQLite DB (deletes all history)
@@ -117,16 +187,34 @@ Commands:
 Aliases:
   bd cancel          same as: bd c
   bd list [N]        same as: bd ls [N]
+  bd forward [N]     same as: bd f [N]
+  bd pick            same as: bd p
+  bd up [NAME|N]     same as: bd u [NAME|N]
   bd help            same as: bd h
   bd -h, bd --help   show this help
 
 Options:
+  bd forget --recursive PATH     also delete every directory below PATH
+  bd forget --all-sessions PATH  delete PATH from every session's history
+  bd prune --dry-run      show what prune would delete
+  bd prune --all          prune every session
+  bd sessions --json      output sessions as machine-readable JSON
   bd doctor --integrity   run SQLite integrity check (can be slow)
   bd doctor --json        output machine-readable JSON
   bd vacuum --yes|--y     skip confirmation prompt (deletes all history)
+  bd --unique ...         count each directory once (also: bd ls --unique [N])
+  bd --scope SCOPE ...    walk history from this session (session, default), all
+                          sessions on this host (host), or all sessions (global);
+                          BD_SCOPE sets the default
+  bd g --regex PATTERN    match PATTERN as a regex (default: glob if it has * ? [,
+                          substring otherwise; globs without / match the last component)
 
 Note:
-  back-directory uses a local SQLite database.
+  back-directory uses a local SQLite database; set BD_DB_PATH to use another file.
+  Set BD_EPHEMERAL=1 to keep this session's history in memory instead (bookmarks,
+  bd q and the maintenance commands need the database and are refused then).
+  Limits and retention (max_back, default_list, ...) are read from
+  ~/.config/back-directory/config.toml; run `bd-core config show` to see them.
 EOF
     return 0
   fi
@@ -138,6 +226,41 @@ EOF
     return 0
   fi
 
+  if [[ $arg == "pause" || $arg == "resume" ]]; then
+    if (( $# > 1 )); then
+      printf '%s\n' "bd: too many arguments"
+      return 2
+    fi
+    _bd_require_core || return 1
+    "$BD_CORE_BIN" session "$arg" --session "$BD_SESSION_ID" || return $?
+    return 0
+  fi
+
+  if [[ $arg == "forget" ]]; then
+    shift
+    if (( $# == 0 )); then
+      printf '%s\n' "bd: forget needs a PATH"
+      return 2
+    fi
+    _bd_require_core || return 1
+    "$BD_CORE_BIN" forget --session "$BD_SESSION_ID" "$@" || return $?
+    return 0
+  fi
+
+  if [[ $arg == "prune" ]]; then
+    shift
+    _bd_require_core || return 1
+    "$BD_CORE_BIN" prune --session "$BD_SESSION_ID" "$@" || return $?
+    return 0
+  fi
+
+  if [[ $arg == "sessions" ]]; then
+    shift
+    _bd_require_core || return 1
+    "$BD_CORE_BIN" sessions --session "$BD_SESSION_ID" "$@" || return $?
+    return 0
+  fi
+
   if [[ $arg == "optimize" ]]; then
     _bd_require_core || return 1
     "$BD_CORE_BIN" optimize || return $?
@@ -169,50 +292,182 @@ EOF
     return $?
   fi
 
+  if [[ $arg == "f" || $arg == "forward" ]]; then
+    if [[ ${2-} == "ls" || ${2-} == "list" ]]; then
+      if (( $# > 3 )); then
+        printf '%s\n' "bd: too many arguments"
+        return 2
+      fi
+      local limit="${3-}"
+      if [[ -n $limit ]] && { ! [[ $limit =~ ^[0-9]+$ ]] || (( limit <= 0 )); }; then
+        printf '%s\n' "bd: usage: bd f ls [N]"
+        return 2
+      fi
+      _bd_require_core || return 1
+      "$BD_CORE_BIN" list --forward --session "$BD_SESSION_ID" --scope "$scope" ${unique:+--unique} ${limit:+--limit=$limit} || return $?
+      return 0
+    fi
+    if (( $# > 2 )); then
+      printf '%s\n' "bd: too many arguments"
+      return 2
+    fi
+    local steps="${2-}"
+    if [[ -z $steps ]]; then
+      steps=1
+    fi
+    if ! [[ $steps =~ ^[0-9]+$ ]] || (( steps <= 0 )); then
+      printf '%s\n' "bd: usage: bd f [N|ls]"
+      return 2
+    fi
+    _bd_require_core || return 1
+    local target
+    target=$("$BD_CORE_BIN" forward --print-path --session "$BD_SESSION_ID" --scope "$scope" ${unique:+--unique} --n "$steps") || return $?
+    BD_SUPPRESS_RECORD=1
+    builtin cd -- "$target"
+    return $?
+  fi
+
+  if [[ $arg == "g" || $arg == "go" ]]; then
+    local mode=auto
+    if [[ ${2-} == "--regex" || ${2-} == "--glob" || ${2-} == "--substring" ]]; then
+      mode=${2#--}
+      shift
+    fi
+    if (( $# != 2 )) || [[ -z ${2-} ]]; then
+      printf '%s\n' "bd: usage: bd g PATTERN"
+      return 2
+    fi
+    _bd_require_core || return 1
+    local target
+    target=$("$BD_CORE_BIN" back --print-path --session "$BD_SESSION_ID" --scope "$scope" ${unique:+--unique} --match "$2" --match-mode "$mode") || return $?
+    BD_SUPPRESS_RECORD=1
+    builtin cd -- "$target"
+    return $?
+  fi
+
+  if [[ $arg == "q" || $arg == "query" ]]; then
+    shift
+    _bd_require_core || return 1
+    if [[ ${1-} == "ls" || ${1-} == "list" ]]; then
+      shift
+      "$BD_CORE_BIN" query --list -- "$@" || return $?
+      return 0
+    fi
+    if (( $# == 0 )); then
+      printf '%s\n' "bd: usage: bd q TERMS..."
+      return 2
+    fi
+    local target
+    target=$("$BD_CORE_BIN" query --exclude "$PWD" -- "$@") || return $?
+    builtin cd -- "$target"
+    return $?
+  fi
+
+  if [[ $arg == @* ]]; then
+    if (( $# > 1 )); then
+      printf '%s\n' "bd: too many arguments"
+      return 2
+    fi
+    _bd_require_core || return 1
+    local target
+    target=$("$BD_CORE_BIN" back --print-path --session "$BD_SESSION_ID" --ago "${arg#@}") || return $?
+    BD_SUPPRESS_RECORD=1
+    builtin cd -- "$target"
+    return $?
+  fi
+
+  if [[ $arg == "p" || $arg == "pick" ]]; then
+    _bd_require_core || return 1
+    local target
+    target=$("$BD_CORE_BIN" pick --session "$BD_SESSION_ID" --scope "$scope" ${unique:+--unique}) || return $?
+    BD_SUPPRESS_RECORD=1
+    builtin cd -- "$target"
+    return $?
+  fi
+
+  if [[ $arg == "m" || $arg == "mark" ]]; then
+    _bd_require_core || return 1
+    case ${2-} in
+      ""|ls|list)
+        "$BD_CORE_BIN" mark list || return $?
+        return 0
+        ;;
+      set|rm)
+        if (( $# != 3 )); then
+          printf '%s\n' "bd: usage: bd m $2 NAME"
+          return 2
+        fi
+        if [[ $2 == "set" ]]; then
+          "$BD_CORE_BIN" mark set --pwd "$PWD" -- "$3" || return $?
+        else
+          "$BD_CORE_BIN" mark rm -- "$3" || return $?
+        fi
+        return 0
+        ;;
+    esac
+    if (( $# > 2 )); then
+      printf '%s\n' "bd: too many arguments"
+      return 2
+    fi
+    local target
+    target=$("$BD_CORE_BIN" mark get -- "$2") || return $?
+    builtin cd -- "$target"
+    return $?
+  fi
+
+  if [[ $arg == "u" || $arg == "up" ]]; then
+    if (( $# > 2 )); then
+      printf '%s\n' "bd: too many arguments"
+      return 2
+    fi
+    _bd_require_core || return 1
+    local target
+    target=$("$BD_CORE_BIN" up --print-path --session "$BD_SESSION_ID" --pwd "$PWD" ${BD_PATH_POLICY:+--path-policy=$BD_PATH_POLICY} -- "${2:-1}") || return $?
+    BD_SUPPRESS_RECORD=1
+    builtin cd -- "$target"
+    return $?
+  fi
+
   if [[ $arg == "ls" || $arg == "list" ]]; then
+    if [[ ${2-} == "--unique" ]]; then
+      unique=1
+      set -- "$1" "${@:3}"
+    fi
     if (( $# > 2 )); then
       printf '%s\n' "bd: too many arguments"
-      return 1
+      return 2
     fi
     local limit="${2-}"
-    if [[ -z $limit ]]; then
-      limit=10
-    fi
-    if ! [[ $limit =~ ^[0-9]+$ ]] || (( limit <= 0 )); then
+    if [[ -n $limit ]] && { ! [[ $limit =~ ^[0-9]+$ ]] || (( limit <= 0 )); }; then
       printf '%s\n' "bd: usage: bd ls [N]"
-      return 1
-    fi
-    if (( limit > BD_MAX_BACK )); then
-      printf '%s\n' "bd: max is $BD_MAX_BACK"
-      return 1
+      return 2
     fi
     _bd_require_core || return 1
-    "$BD_CORE_BIN" list --session "$BD_SESSION_ID" --limit "$limit" || return $?
+    "$BD_CORE_BIN" list --session "$BD_SESSION_ID" --scope "$scope" ${unique:+--unique} ${limit:+--limit=$limit} || return $?
     return 0
   fi
 
   if (( $# > 1 )); then
     printf '%s\n' "bd: too many arguments"
-    return 1
+    return 2
   fi
 
   if ! [[ $arg =~ ^[0-9]+$ ]] || (( arg <= 0 )); then
     printf '%s\n' "bd: usage: bd [N|c|ls]"
-    return 1
-  fi
-
-  if (( arg > BD_MAX_BACK )); then
-    printf '%s\n' "bd: max is $BD_MAX_BACK"
-    return 1
+    return 2
   fi
 
   _bd_require_core || return 1
   local target
-  target=$("$BD_CORE_BIN" back --print-path --session "$BD_SESSION_ID" --n "$arg") || return $?
+  target=$("$BD_CORE_BIN" back --print-path --session "$BD_SESSION_ID" --scope "$scope" ${unique:+--unique} --n "$arg") || return $?
   BD_SUPPRESS_RECORD=1
   builtin cd -- "$target"
 }
 
 _bd_add_prompt_command
 BD_LAST_PWD=$PWD
-_bd_record >/dev/null 2>&1
+if [[ -n $_bd_parent_session_id ]]; then
+  _bd_record_hook --inherit-from "$_bd_parent_session_id"
+else
+  _bd_record_hook
+fi
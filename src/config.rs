@@ -0,0 +1,363 @@
+//! User configuration: `$XDG_CONFIG_HOME/back-directory/config.toml`.
+//!
+//! Every key is optional; anything left out keeps its built-in default. Unknown keys and
+//! out-of-range values are rejected rather than ignored, so a typo cannot silently fall
+//! back to the default.
+
+use crate::history::{format_duration, parse_duration};
+use crate::ignore::config_rules;
+use crate::{Error, BD_DEFAULT_LIST, BD_MAX_BACK};
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+pub(crate) const CLEANUP_INTERVAL_SECS: i64 = 10 * 24 * 60 * 60; //  10 days
+pub(crate) const SESSION_RETENTION_SECS: i64 = 180 * 24 * 60 * 60; // 180 days
+pub(crate) const UNDO_RETENTION_SECS: i64 = 90 * 24 * 60 * 60; //  90 days
+/// Number of events kept per session before the oldest are rotated away.
+pub(crate) const EVENTS_PER_SESSION: u32 = 10_000;
+
+/// Where an effective setting came from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File,
+}
+
+impl Source {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Source::Default => "default",
+            Source::File => "config",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+impl<T> Setting<T> {
+    fn new(value: T) -> Self {
+        Setting {
+            value,
+            source: Source::Default,
+        }
+    }
+
+    fn set(&mut self, value: Option<T>) {
+        if let Some(value) = value {
+            self.value = value;
+            self.source = Source::File;
+        }
+    }
+}
+
+/// A setting's value, as written in the config file.
+pub enum SettingValue {
+    Count(u32),
+    /// Seconds, written like `10d`.
+    Duration(i64),
+    /// Glob patterns, written as an array of strings.
+    Patterns(Vec<String>),
+}
+
+impl SettingValue {
+    /// The value as a TOML (and JSON) literal.
+    pub fn literal(&self) -> String {
+        match self {
+            SettingValue::Count(_) | SettingValue::Patterns(_) => self.to_string(),
+            SettingValue::Duration(_) => quote(&self.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for SettingValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingValue::Count(count) => write!(f, "{count}"),
+            SettingValue::Duration(secs) => f.write_str(&format_duration(*secs)),
+            SettingValue::Patterns(patterns) => {
+                let quoted: Vec<String> = patterns.iter().map(|p| quote(p)).collect();
+                write!(f, "[{}]", quoted.join(", "))
+            }
+        }
+    }
+}
+
+/// A string literal that is valid in both TOML and JSON.
+fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Effective limits and retention periods.
+#[derive(Clone)]
+pub struct Config {
+    /// The file consulted, whether or not it exists; `None` for built-in defaults.
+    pub path: Option<PathBuf>,
+    /// Whether `path` existed and was read.
+    pub loaded: bool,
+    /// Largest step count accepted by `back`, `forward`, `list` and `pick`.
+    pub max_back: Setting<u32>,
+    /// Entries shown by `list` and `query --list` when no limit is given.
+    pub default_list: Setting<u32>,
+    /// Events kept per session before the oldest are rotated away.
+    pub events_per_session: Setting<u32>,
+    /// Minimum time between two retention cleanups.
+    pub cleanup_interval: Setting<i64>,
+    /// Sessions idle for longer than this are deleted by cleanup.
+    pub session_retention: Setting<i64>,
+    /// Cancel stack entries older than this are deleted by cleanup.
+    pub undo_retention: Setting<i64>,
+    /// Directories `record` leaves out of history (see [`Config::check_ignore`]).
+    pub exclude: Setting<Vec<String>>,
+    /// Exceptions to `exclude`.
+    pub include: Setting<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    max_back: Option<u32>,
+    default_list: Option<u32>,
+    events_per_session: Option<u32>,
+    cleanup_interval: Option<String>,
+    session_retention: Option<String>,
+    undo_retention: Option<String>,
+    exclude: Option<Vec<String>>,
+    include: Option<Vec<String>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            path: None,
+            loaded: false,
+            max_back: Setting::new(BD_MAX_BACK),
+            default_list: Setting::new(BD_DEFAULT_LIST),
+            events_per_session: Setting::new(EVENTS_PER_SESSION),
+            cleanup_interval: Setting::new(CLEANUP_INTERVAL_SECS),
+            session_retention: Setting::new(SESSION_RETENTION_SECS),
+            undo_retention: Setting::new(UNDO_RETENTION_SECS),
+            exclude: Setting::new(Vec::new()),
+            include: Setting::new(Vec::new()),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the user's config file (see [`config_path`]); defaults if it does not exist.
+    pub fn load() -> Result<Self, Error> {
+        Self::load_from(&config_path()?)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, Error> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Config {
+                    path: Some(path.to_path_buf()),
+                    ..Config::default()
+                });
+            }
+            Err(err) => {
+                return Err(Error::Config(format!("{}: {err}", path.display())));
+            }
+        };
+        Self::parse(&text, path)
+    }
+
+    /// Parses the contents of a config file; `path` is only used in error messages.
+    pub fn parse(text: &str, path: &Path) -> Result<Self, Error> {
+        let invalid = |msg: String| Error::Config(format!("{}: {msg}", path.display()));
+        let raw: RawConfig = toml::from_str(text).map_err(|err| {
+            let line = err
+                .span()
+                .map(|span| text[..span.start].matches('\n').count() + 1);
+            let msg = err.message().trim_end().replace('\n', ": ");
+            invalid(match line {
+                Some(line) => format!("line {line}: {msg}"),
+                None => msg.to_string(),
+            })
+        })?;
+        let duration = |key: &str, value: Option<String>| -> Result<Option<i64>, Error> {
+            value
+                .map(|value| {
+                    parse_duration(&value).map_err(|_| {
+                        invalid(format!(
+                            "{key}: invalid duration {value:?} (use e.g. 30s, 15m, 2h, 1d)"
+                        ))
+                    })
+                })
+                .transpose()
+        };
+
+        let mut config = Config {
+            path: Some(path.to_path_buf()),
+            loaded: true,
+            ..Config::default()
+        };
+        config.max_back.set(raw.max_back);
+        config.default_list.set(raw.default_list);
+        config.events_per_session.set(raw.events_per_session);
+        config
+            .cleanup_interval
+            .set(duration("cleanup_interval", raw.cleanup_interval)?);
+        config
+            .session_retention
+            .set(duration("session_retention", raw.session_retention)?);
+        config
+            .undo_retention
+            .set(duration("undo_retention", raw.undo_retention)?);
+        config.exclude.set(raw.exclude);
+        config.include.set(raw.include);
+
+        for (key, value) in [
+            ("max_back", config.max_back.value),
+            ("default_list", config.default_list.value),
+            ("events_per_session", config.events_per_session.value),
+        ] {
+            if value == 0 {
+                return Err(invalid(format!("{key} must be at least 1")));
+            }
+        }
+        if config.default_list.value > config.max_back.value {
+            // Only an explicit default_list conflicts; the built-in one shrinks to fit.
+            if config.default_list.source == Source::Default {
+                config.default_list.value = config.max_back.value;
+            } else {
+                return Err(invalid(format!(
+                    "default_list ({}) is larger than max_back ({})",
+                    config.default_list.value, config.max_back.value
+                )));
+            }
+        }
+        config_rules(&config).map_err(invalid)?;
+        Ok(config)
+    }
+
+    /// Every setting in config-file order, with its value and source.
+    pub fn settings(&self) -> [(&'static str, SettingValue, Source); 8] {
+        let count = |setting: &Setting<u32>| (SettingValue::Count(setting.value), setting.source);
+        let duration =
+            |setting: &Setting<i64>| (SettingValue::Duration(setting.value), setting.source);
+        let patterns = |setting: &Setting<Vec<String>>| {
+            (
+                SettingValue::Patterns(setting.value.clone()),
+                setting.source,
+            )
+        };
+        let entry = |key, (value, source)| (key, value, source);
+        [
+            entry("max_back", count(&self.max_back)),
+            entry("default_list", count(&self.default_list)),
+            entry("events_per_session", count(&self.events_per_session)),
+            entry("cleanup_interval", duration(&self.cleanup_interval)),
+            entry("session_retention", duration(&self.session_retention)),
+            entry("undo_retention", duration(&self.undo_retention)),
+            entry("exclude", patterns(&self.exclude)),
+            entry("include", patterns(&self.include)),
+        ]
+    }
+}
+
+/// `$XDG_CONFIG_HOME/back-directory/config.toml`, or `~/.config/back-directory/config.toml`.
+pub fn config_path() -> Result<PathBuf, Error> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home = env::var("HOME").map_err(|_| Error::Other("HOME not set".to_string()))?;
+            PathBuf::from(home).join(".config")
+        }
+    };
+    Ok(dir.join("back-directory").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Config, Error> {
+        Config::parse(text, Path::new("config.toml"))
+    }
+
+    #[test]
+    fn an_empty_file_keeps_the_defaults() {
+        let config = parse("").unwrap();
+        assert!(config.loaded);
+        assert_eq!(config.max_back.value, BD_MAX_BACK);
+        assert_eq!(config.default_list.value, BD_DEFAULT_LIST);
+        assert_eq!(config.cleanup_interval.value, CLEANUP_INTERVAL_SECS);
+        assert!(config
+            .settings()
+            .iter()
+            .all(|(_, _, source)| *source == Source::Default));
+    }
+
+    #[test]
+    fn values_from_the_file_override_the_defaults() {
+        let config = parse(
+            "max_back = 50\ndefault_list = 5\nsession_retention = \"30d\"\nexclude = [\"target\"]\n",
+        )
+        .unwrap();
+        assert_eq!(config.max_back.value, 50);
+        assert!(config.max_back.source == Source::File);
+        assert_eq!(config.default_list.value, 5);
+        assert_eq!(config.session_retention.value, 30 * 24 * 60 * 60);
+        assert_eq!(config.exclude.value, ["target"]);
+        assert!(config.events_per_session.source == Source::Default);
+    }
+
+    #[test]
+    fn default_list_shrinks_to_a_smaller_max_back() {
+        let config = parse("max_back = 2\n").unwrap();
+        assert_eq!(config.default_list.value, 2);
+        assert!(config.default_list.source == Source::Default);
+    }
+
+    #[test]
+    fn an_explicit_default_list_above_max_back_is_rejected() {
+        assert!(matches!(
+            parse("max_back = 2\ndefault_list = 5\n"),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        for text in [
+            "max_back = 0\n",
+            "events_per_session = 0\n",
+            "max_back = -1\n",
+            "cleanup_interval = \"soon\"\n",
+            "undo_retention = 90\n",
+            "unknown = 1\n",
+            "max_back = \n",
+        ] {
+            assert!(matches!(parse(text), Err(Error::Config(_))), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn syntax_errors_name_the_line() {
+        let Err(Error::Config(msg)) = parse("max_back = 50\nnope\n") else {
+            panic!("expected a config error");
+        };
+        assert!(msg.starts_with("config.toml: line 2:"), "{msg}");
+    }
+}
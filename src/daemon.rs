@@ -0,0 +1,294 @@
+//! Transport for `bd-core daemon`, a resident process that keeps the database open.
+//!
+//! The daemon listens on a Unix socket next to the database (`<db>.sock`). A client sends
+//! a protocol header, and once the daemon echoes it back, its command-line arguments; it
+//! gets back what the command would have printed and its exit status. Connections are
+//! served one at a time, so commands never run concurrently inside the daemon. Which commands are forwarded, and how they run, is up
+//! to the caller of [`forward`] and [`serve`].
+
+use crate::spool::lock;
+use crate::store::{escape, unescape};
+use crate::Error;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const PROTOCOL_HEADER: &str = "bd-daemon 2";
+/// How long a client waits for the daemon to acknowledge the header.
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long the daemon waits for a client to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a client waits for the answer; longer than the database busy timeout.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a command run by the daemon printed, and how it exited.
+pub struct Response {
+    pub code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// The socket of the daemon serving the database at `db`.
+pub fn socket_path(db: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.sock", db.display()))
+}
+
+/// Sends `args` to the daemon listening on `socket`. Returns `None` when no daemon is
+/// listening or it declined the request (it does not speak this protocol version, or
+/// runs as another user); nothing has run then, and the caller runs the command itself.
+/// The arguments are only sent once the daemon has acknowledged the header, and any
+/// failure after that, including an empty or cut-off answer, is an error, so a command
+/// never runs twice.
+pub fn forward(socket: &Path, args: &[String]) -> Result<Option<Response>, Error> {
+    let Ok(mut stream) = UnixStream::connect(socket) else {
+        return Ok(None);
+    };
+    if stream.set_read_timeout(Some(ACK_TIMEOUT)).is_err()
+        || stream
+            .write_all(format!("{PROTOCOL_HEADER}\n").as_bytes())
+            .is_err()
+    {
+        return Ok(None);
+    }
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut ack = String::new();
+    if reader.read_line(&mut ack).is_err() || ack.trim_end_matches('\n') != PROTOCOL_HEADER {
+        return Ok(None);
+    }
+
+    let failed = |err: std::io::Error| Error::Other(format!("bd-core daemon: {err}"));
+    stream
+        .set_read_timeout(Some(RESPONSE_TIMEOUT))
+        .map_err(failed)?;
+    let mut request = String::new();
+    for arg in args {
+        request.push_str(&escape(arg));
+        request.push('\n');
+    }
+    stream.write_all(request.as_bytes()).map_err(failed)?;
+    stream.shutdown(std::net::Shutdown::Write).map_err(failed)?;
+
+    let mut text = String::new();
+    reader.read_to_string(&mut text).map_err(failed)?;
+    let malformed = || {
+        Error::Other(if text.is_empty() {
+            "bd-core daemon: no answer".to_string()
+        } else {
+            "bd-core daemon: malformed response".to_string()
+        })
+    };
+    // Every field ends in a newline, so a cut-off answer is missing at least one.
+    let fields: Vec<&str> = text.split_terminator('\n').collect();
+    let [code, stdout, stderr] = fields[..] else {
+        return Err(malformed());
+    };
+    if !text.ends_with('\n') {
+        return Err(malformed());
+    }
+    let code = code.parse().map_err(|_| malformed())?;
+    Ok(Some(Response {
+        code,
+        stdout: unescape(stdout),
+        stderr: unescape(stderr),
+    }))
+}
+
+/// Listens on `socket` and answers each request with `handle(args)`, until the process
+/// is killed. An exclusive lock on `<socket>.lock`, held while serving, keeps a second
+/// daemon from starting on the same database; a socket left behind by one that died is
+/// replaced. Only processes running as the daemon's own user are served.
+pub fn serve(socket: &Path, mut handle: impl FnMut(Vec<String>) -> Response) -> Result<(), Error> {
+    let lock_path = PathBuf::from(format!("{}.lock", socket.display()));
+    let lock_file = {
+        use std::os::unix::fs::OpenOptionsExt;
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(&lock_path)
+            .map_err(|err| Error::Io(format!("{}: {err}", lock_path.display())))?
+    };
+    if !lock(&lock_file, false)? {
+        return Err(Error::Other(format!(
+            "bd-core daemon is already running on {}",
+            socket.display()
+        )));
+    }
+    match std::fs::remove_file(socket) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    // bind() creates the socket file under the umask. Setting it first makes the socket
+    // 0600 from the start, rather than open to other users until a later chmod. The daemon
+    // has not started any threads yet, so nothing else sees the changed umask.
+    // SAFETY: umask only swaps the process file mode creation mask.
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(socket);
+    // SAFETY: as above.
+    unsafe { libc::umask(umask) };
+    let listener = listener.map_err(|err| Error::Io(format!("{}: {err}", socket.display())))?;
+    // SAFETY: geteuid cannot fail.
+    let uid = unsafe { libc::geteuid() };
+
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        // Closing without an acknowledgement makes another user's client run the
+        // command itself, against its own permissions.
+        if peer_uid(&stream).map_or(true, |peer| peer != uid) {
+            continue;
+        }
+        // A client that hangs up or sends garbage only loses its own request.
+        let _ = answer(stream, &mut handle);
+    }
+    drop(lock_file);
+    Ok(())
+}
+
+fn answer(
+    mut stream: UnixStream,
+    handle: &mut impl FnMut(Vec<String>) -> Response,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut lines = BufReader::new(stream.try_clone()?).lines();
+    if lines.next().transpose()?.as_deref() != Some(PROTOCOL_HEADER) {
+        // Closing without an acknowledgement makes a client of another version run the
+        // command itself.
+        return Ok(());
+    }
+    stream.write_all(format!("{PROTOCOL_HEADER}\n").as_bytes())?;
+    let args = lines
+        .map(|line| line.map(|line| unescape(&line)))
+        .collect::<std::io::Result<Vec<String>>>()?;
+
+    let response = handle(args);
+    let text = format!(
+        "{}\n{}\n{}\n",
+        response.code,
+        escape(&response.stdout),
+        escape(&response.stderr)
+    );
+    stream.write_all(text.as_bytes())
+}
+
+/// The effective user id of the process at the other end of `stream`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<libc::uid_t> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: `cred` and `len` are valid for writes and `len` holds the size of `cred`.
+    let rc = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&mut cred as *mut libc::ucred).cast(),
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+/// The effective user id of the process at the other end of `stream`.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<libc::uid_t> {
+    let mut uid = 0;
+    let mut gid = 0;
+    // SAFETY: `uid` and `gid` are valid for writes.
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(uid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::tests::TempDirs;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn requests_are_answered_by_the_handler() {
+        let dirs = TempDirs::new("daemon", &[]);
+        let socket = dirs.root.join("bd.sock");
+        let served = socket.clone();
+        std::thread::spawn(move || {
+            serve(&served, |args| Response {
+                code: args.len() as i32,
+                stdout: args.join("\n"),
+                stderr: "warning\tnone".to_string(),
+            })
+        });
+
+        // Wait for the listener to come up.
+        let response = (0..500)
+            .find_map(|_| {
+                std::thread::sleep(Duration::from_millis(10));
+                forward(&socket, &args(&["list", "a\\b"])).unwrap()
+            })
+            .expect("the daemon did not start");
+        assert_eq!(response.code, 2);
+        assert_eq!(response.stdout, "list\na\\b");
+        assert_eq!(response.stderr, "warning\tnone");
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // A second daemon on the same socket is refused and leaves the first one alone.
+        let second = serve(&socket, |_| unreachable!());
+        assert!(matches!(second, Err(Error::Other(_))));
+        assert!(forward(&socket, &args(&["list"])).unwrap().is_some());
+    }
+
+    #[test]
+    fn without_a_daemon_the_caller_runs_the_command() {
+        let dirs = TempDirs::new("no-daemon", &[]);
+        let socket = dirs.root.join("bd.sock");
+        assert!(forward(&socket, &args(&["list"])).unwrap().is_none());
+    }
+
+    #[test]
+    fn a_request_dropped_after_the_acknowledgement_is_an_error() {
+        let dirs = TempDirs::new("dropped", &[]);
+        let socket = dirs.root.join("bd.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        std::thread::spawn(move || {
+            let mut streams = listener.incoming();
+            // The first connection is closed before the acknowledgement.
+            drop(streams.next());
+            // The second is acknowledged, then closed without an answer.
+            let mut stream = streams.next().unwrap().unwrap();
+            let mut header = String::new();
+            BufReader::new(stream.try_clone().unwrap())
+                .read_line(&mut header)
+                .unwrap();
+            stream
+                .write_all(format!("{PROTOCOL_HEADER}\n").as_bytes())
+                .unwrap();
+        });
+
+        assert!(forward(&socket, &args(&["list"])).unwrap().is_none());
+        assert!(matches!(
+            forward(&socket, &args(&["list"])),
+            Err(Error::Other(_))
+        ));
+    }
+}
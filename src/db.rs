@@ -0,0 +1,65 @@
+//! SQLite database location, connection setup, and shared constants.
+
+use crate::migrate::migrate;
+use crate::Error;
+use rusqlite::Connection;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub(crate) const META_LAST_CLEANUP_KEY: &str = "last_cleanup_at";
+/// How long a statement waits for another process's write lock before failing with
+/// [`Error::Locked`]. Kept short because `record` runs on every prompt.
+const BUSY_TIMEOUT: Duration = Duration::from_millis(500);
+
+pub(crate) fn open_db(path: &Path) -> Result<Connection, Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut conn = Connection::open(path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.execute_batch(
+        "PRAGMA journal_mode = WAL;
+         PRAGMA synchronous = NORMAL;
+         PRAGMA temp_store = MEMORY;",
+    )?;
+    migrate(&mut conn)?;
+    Ok(conn)
+}
+
+/// `$XDG_STATE_HOME/back-directory`, or `~/.local/state/back-directory`.
+pub fn xdg_state_dir() -> Result<PathBuf, Error> {
+    if let Ok(state_home) = env::var("XDG_STATE_HOME") {
+        return Ok(PathBuf::from(state_home).join("back-directory"));
+    }
+
+    let home = env::var("HOME").map_err(|_| Error::Other("HOME not set".to_string()))?;
+    Ok(PathBuf::from(home)
+        .join(".local")
+        .join("state")
+        .join("back-directory"))
+}
+
+/// Default location of the history database.
+pub fn db_path() -> Result<PathBuf, Error> {
+    Ok(xdg_state_dir()?.join("bd.sqlite3"))
+}
+
+pub(crate) fn current_host() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for `buf.len()` bytes and gethostname writes at most that.
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    if rc != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+pub(crate) fn current_ts() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
@@ -0,0 +1,154 @@
+//! Database health reporting and maintenance.
+
+use crate::db::{open_db, META_LAST_CLEANUP_KEY};
+use crate::spool::{spool_path, spooled};
+use crate::store::SqliteStore;
+use crate::{Config, Error, History};
+use rusqlite::{params, OptionalExtension};
+use std::path::{Path, PathBuf};
+
+/// Snapshot of the database file, table sizes and configuration reported by `bd doctor`.
+pub struct DoctorReport {
+    pub path: PathBuf,
+    pub db_size: Option<u64>,
+    pub wal_size: Option<u64>,
+    pub shm_size: Option<u64>,
+    pub page_count: i64,
+    pub freelist_count: i64,
+    pub page_size: i64,
+    /// `PRAGMA user_version`, i.e. the last migration applied.
+    pub schema_version: i64,
+    pub events: i64,
+    /// Distinct directories the events refer to.
+    pub dirs: i64,
+    pub sessions: i64,
+    pub undo_moves: i64,
+    pub marks: i64,
+    /// Unix time of the last retention cleanup, or 0 if it never ran.
+    pub last_cleanup_at: i64,
+    /// Records waiting in the spool for the database to be unlocked.
+    pub spooled: usize,
+    /// Rows of `PRAGMA integrity_check`, when requested.
+    pub integrity: Option<Vec<String>>,
+    /// The effective limits and retention periods.
+    pub config: Config,
+}
+
+impl DoctorReport {
+    /// `VACUUM` is worth running once a fifth of the pages are free.
+    pub fn optimize_recommended(&self) -> bool {
+        if self.page_count > 0 {
+            (self.freelist_count as f64 / self.page_count as f64) >= 0.2
+        } else {
+            false
+        }
+    }
+}
+
+impl History {
+    /// Reports the state of the database without changing it, so records waiting in the
+    /// spool are counted rather than replayed.
+    pub fn doctor(&self, integrity: bool) -> Result<DoctorReport, Error> {
+        let store = self.sqlite()?;
+        let conn = store.connection();
+        let path = store.path().to_path_buf();
+        let (wal_path, shm_path) = sidecar_paths(&path);
+
+        let pragma = |name: &str| -> Result<i64, Error> {
+            conn.query_row(&format!("PRAGMA {name}"), [], |row| row.get(0))
+                .map_err(Error::from)
+        };
+        let count = |table: &str| -> Result<i64, Error> {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .map_err(Error::from)
+        };
+
+        let last_cleanup_at: i64 = conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![META_LAST_CLEANUP_KEY],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or(0);
+
+        let integrity = if integrity {
+            let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            let mut results = Vec::new();
+            for row in rows {
+                results.push(row?);
+            }
+            Some(results)
+        } else {
+            None
+        };
+
+        Ok(DoctorReport {
+            db_size: file_size(&path),
+            wal_size: file_size(&wal_path),
+            shm_size: file_size(&shm_path),
+            path,
+            page_count: pragma("page_count")?,
+            freelist_count: pragma("freelist_count")?,
+            page_size: pragma("page_size")?,
+            schema_version: pragma("user_version")?,
+            events: count("events")?,
+            dirs: count("dirs")?,
+            sessions: count("sessions")?,
+            undo_moves: count("undo_moves")?,
+            marks: count("marks")?,
+            last_cleanup_at,
+            spooled: spooled(store.path()),
+            integrity,
+            config: self.config().clone(),
+        })
+    }
+
+    fn sqlite(&self) -> Result<&SqliteStore, Error> {
+        self.store.as_sqlite().ok_or_else(|| {
+            Error::Usage("this command needs the database, not an ephemeral store".to_string())
+        })
+    }
+
+    /// Rebuilds the database file to reclaim free pages, then checkpoints and truncates the
+    /// WAL so deleted rows do not survive there either.
+    pub fn optimize(&self) -> Result<(), Error> {
+        let conn = self.sqlite()?.connection();
+        conn.execute_batch("VACUUM;")?;
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        Ok(())
+    }
+}
+
+/// Deletes the database at `path` (with its WAL files and spool) and recreates it empty.
+pub fn reset(path: &Path) -> Result<(), Error> {
+    let (wal_path, shm_path) = sidecar_paths(path);
+    remove_file_if_exists(path)?;
+    remove_file_if_exists(&wal_path)?;
+    remove_file_if_exists(&shm_path)?;
+    remove_file_if_exists(&spool_path(path))?;
+    let _ = open_db(path)?;
+    Ok(())
+}
+
+fn sidecar_paths(path: &Path) -> (PathBuf, PathBuf) {
+    (
+        PathBuf::from(format!("{}-wal", path.display())),
+        PathBuf::from(format!("{}-shm", path.display())),
+    )
+}
+
+fn remove_file_if_exists(path: &Path) -> Result<(), Error> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn file_size(path: &Path) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|meta| meta.len())
+}
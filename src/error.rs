@@ -0,0 +1,93 @@
+//! Error type shared by the library and the `bd-core` binary.
+
+use std::fmt;
+
+/// Why a request failed. Each kind has its own process exit code (see
+/// [`Error::exit_code`]) so the wrappers and scripts can tell them apart.
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid arguments, such as `N` out of range or a malformed pattern. Exit code 2.
+    Usage(String),
+    /// History (or the marks table) has nothing matching the request. Exit code 3.
+    EmptyHistory(String),
+    /// The cancel stack is empty, or the directory it would return to is gone. Exit code 4.
+    NothingToCancel,
+    /// A directory the request depends on does not exist. Exit code 5.
+    MissingDirectory(String),
+    /// Another process held the database lock for too long. Exit code 6.
+    Locked(String),
+    /// The database file is damaged or is not a SQLite database. Exit code 7.
+    Corrupt(String),
+    /// The database was written by a newer `bd-core` with a schema this build does not
+    /// know. Exit code 8.
+    SchemaTooNew { found: i64, supported: i64 },
+    /// The config file could not be read or has an invalid key or value. Exit code 9.
+    Config(String),
+    /// Any other database failure. Exit code 1.
+    Db(String),
+    /// A file or socket outside the database, such as the spool or the daemon socket,
+    /// could not be read or written. Exit code 1.
+    Io(String),
+    /// Anything else, such as a cancelled picker. Exit code 1.
+    Other(String),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Usage(_) => 2,
+            Error::EmptyHistory(_) => 3,
+            Error::NothingToCancel => 4,
+            Error::MissingDirectory(_) => 5,
+            Error::Locked(_) => 6,
+            Error::Corrupt(_) => 7,
+            Error::SchemaTooNew { .. } => 8,
+            Error::Config(_) => 9,
+            Error::Db(_) | Error::Io(_) | Error::Other(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Usage(msg)
+            | Error::EmptyHistory(msg)
+            | Error::MissingDirectory(msg)
+            | Error::Other(msg) => write!(f, "bd: {msg}"),
+            Error::NothingToCancel => write!(f, "bd: nothing to cancel"),
+            Error::SchemaTooNew { found, supported } => write!(
+                f,
+                "bd: database schema version {found} is newer than this bd-core supports ({supported}); upgrade bd-core"
+            ),
+            Error::Locked(msg) | Error::Corrupt(msg) | Error::Db(msg) => {
+                write!(f, "bd: db error: {msg}")
+            }
+            Error::Io(msg) => write!(f, "bd: i/o error: {msg}"),
+            Error::Config(msg) => write!(f, "bd: config error: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        use rusqlite::ErrorCode;
+        match err.sqlite_error_code() {
+            Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => {
+                Error::Locked(err.to_string())
+            }
+            Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase) => {
+                Error::Corrupt(err.to_string())
+            }
+            _ => Error::Db(err.to_string()),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err.to_string())
+    }
+}
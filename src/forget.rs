@@ -0,0 +1,290 @@
+//! Removing directories from recorded history: `forget` and `prune`.
+
+use crate::history::{scan_session, PAST_NEWEST};
+use crate::paths::normalize_logical;
+use crate::store::{EventFilter, EventRow, EventScan, Store};
+use crate::{Error, History};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+/// What [`History::forget`] removed.
+pub struct ForgetReport {
+    /// Distinct directories removed, as recorded.
+    pub paths: Vec<String>,
+    pub events: usize,
+    /// Sessions whose cursor or last move referred to a removed event.
+    pub sessions_repaired: usize,
+    /// Cancel stack entries dropped because they referred to a removed event.
+    pub undo_removed: usize,
+}
+
+/// What [`History::prune`] removed, or would remove with `dry_run`.
+pub struct PruneReport {
+    /// Missing directories with the number of events removed for each.
+    pub paths: Vec<(String, usize)>,
+    pub events: usize,
+    /// Events of missing directories kept because a cursor or cancel entry refers to them.
+    pub kept: usize,
+}
+
+impl History {
+    /// Deletes the events recorded for `path` (and, with `recursive`, every directory
+    /// below it) from the history of `session`, or from every session when `session` is
+    /// `None`. Entries `session` inherited are deleted from the session they belong to, so
+    /// they also disappear from it. Events match on either the recorded or the canonical
+    /// path. `path` must be absolute.
+    ///
+    /// A cursor left on a removed event moves to the next remaining event of its session
+    /// (or past the newest), so walking back visits the same remaining directories. Last
+    /// moves and cancel entries that refer to removed events are dropped. Records still
+    /// waiting in the spool are stored first, so a spooled visit is forgotten too.
+    pub fn forget(
+        &mut self,
+        session: Option<&str>,
+        path: &str,
+        recursive: bool,
+    ) -> Result<ForgetReport, Error> {
+        let path = normalize_logical(path)?;
+        let real_path = std::fs::canonicalize(&path)
+            .map(|real| real.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| path.clone());
+        self.replay_spool()?;
+        let covers = |recorded: &str| {
+            [path.as_str(), real_path.as_str()]
+                .iter()
+                .any(|target| within(recorded, target, recursive))
+        };
+
+        self.atomic(|store| {
+            let mut removed = Vec::new();
+            let mut paths = BTreeSet::new();
+            scan_scope(store, session, &mut |event| {
+                if covers(&event.path) || covers(&event.real_path) {
+                    removed.push(event.id);
+                    paths.insert(event.path);
+                }
+                true
+            })?;
+            if removed.is_empty() {
+                return Err(Error::EmptyHistory(format!("no history matches {path}")));
+            }
+            store.delete_events(&removed)?;
+            let removed: HashSet<i64> = removed.into_iter().collect();
+
+            let mut sessions_repaired = 0;
+            let mut undo_removed = 0;
+            for row in store.sessions()? {
+                let cursor_removed = removed.contains(&row.cursor_id);
+                if cursor_removed
+                    || removed.contains(&row.last_from_id)
+                    || removed.contains(&row.last_to_id)
+                {
+                    let cursor_id = if cursor_removed {
+                        next_event(store, &row.session, row.cursor_id)?.unwrap_or(PAST_NEWEST)
+                    } else {
+                        row.cursor_id
+                    };
+                    store.set_cursor(&row.session, cursor_id, None, row.last_seen_at)?;
+                    sessions_repaired += 1;
+                }
+                for undo in store.undo_moves(&row.session)? {
+                    if removed.contains(&undo.from_id) || removed.contains(&undo.to_id) {
+                        store.remove_undo(undo.id)?;
+                        undo_removed += 1;
+                    }
+                }
+            }
+
+            Ok(ForgetReport {
+                paths: paths.into_iter().collect(),
+                events: removed.len(),
+                sessions_repaired,
+                undo_removed,
+            })
+        })
+    }
+
+    /// Deletes the events in the history of `session`, inherited entries included (or of
+    /// every session when `None`), whose directory no longer exists. As with rotation, an event that any session's cursor, last move
+    /// or cancel stack still refers to is kept. With `dry_run` nothing is deleted, but
+    /// records still waiting in the spool are stored first either way.
+    pub fn prune(&mut self, session: Option<&str>, dry_run: bool) -> Result<PruneReport, Error> {
+        self.replay_spool()?;
+        self.atomic(|store| {
+            let mut referenced = HashSet::new();
+            for row in store.sessions()? {
+                referenced.extend([row.cursor_id, row.last_from_id, row.last_to_id]);
+                for undo in store.undo_moves(&row.session)? {
+                    referenced.extend([undo.from_id, undo.to_id]);
+                }
+            }
+
+            let mut exists: HashMap<String, bool> = HashMap::new();
+            let mut removed = Vec::new();
+            let mut paths: BTreeMap<String, usize> = BTreeMap::new();
+            let mut kept = 0;
+            scan_scope(store, session, &mut |event| {
+                let is_dir = *exists
+                    .entry(event.path.clone())
+                    .or_insert_with(|| Path::new(&event.path).is_dir());
+                if is_dir {
+                    return true;
+                }
+                if referenced.contains(&event.id) {
+                    kept += 1;
+                } else {
+                    removed.push(event.id);
+                    *paths.entry(event.path).or_default() += 1;
+                }
+                true
+            })?;
+            if !dry_run {
+                store.delete_events(&removed)?;
+            }
+            Ok(PruneReport {
+                paths: paths.into_iter().collect(),
+                events: removed.len(),
+                kept,
+            })
+        })
+    }
+}
+
+/// Visits, oldest first, every event in the history of `session` (including the entries it
+/// inherited from the session it was started from), or every event when `None`.
+fn scan_scope(
+    store: &dyn Store,
+    session: Option<&str>,
+    visit: &mut dyn FnMut(EventRow) -> bool,
+) -> Result<(), Error> {
+    match session {
+        Some(session) => scan_session(store, session, false, 0, None, visit),
+        None => store.scan_events(
+            &EventScan {
+                filter: EventFilter::All,
+                backward: false,
+                from_id: 0,
+                max_ts: None,
+            },
+            visit,
+        ),
+    }
+}
+
+/// Whether `recorded` is `dir`, or with `recursive` lies below it.
+fn within(recorded: &str, dir: &str, recursive: bool) -> bool {
+    if recorded == dir {
+        return true;
+    }
+    recursive
+        && recorded
+            .strip_prefix(dir.trim_end_matches('/'))
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// The oldest event of `session` newer than `id`, in the history it walks.
+fn next_event(store: &dyn Store, session: &str, id: i64) -> Result<Option<i64>, Error> {
+    let mut next = None;
+    scan_session(store, session, false, id, None, &mut |event| {
+        next = Some(event.id);
+        false
+    })?;
+    Ok(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::history::tests::{events, history, listed, record, TempDirs};
+    use crate::store::SqliteStore;
+    use crate::{History, HistoryView, PathPolicy};
+    use std::fs;
+
+    #[test]
+    fn forget_removes_a_subtree_and_repairs_the_cursor() {
+        let dirs = TempDirs::new("forget", &["a", "b/x", "c"]);
+        let mut history = history("");
+        record(&mut history, &dirs, &["a", "b", "b/x", "c"]);
+        history.back(&HistoryView::session("s"), 1).unwrap();
+
+        let report = history.forget(None, &dirs.path("b"), true).unwrap();
+        assert_eq!(report.paths, [dirs.path("b"), dirs.path("b/x")]);
+        assert_eq!(report.events, 2);
+        assert_eq!(report.sessions_repaired, 1);
+        assert_eq!(report.undo_removed, 1);
+        assert_eq!(events(&history, "s"), 2);
+        // The cursor moved on to the next remaining event.
+        assert_eq!(
+            listed(&mut history, &HistoryView::session("s")),
+            [dirs.path("a")]
+        );
+    }
+
+    #[test]
+    fn forget_without_recursive_keeps_subdirectories() {
+        let dirs = TempDirs::new("forget-one", &["b/x"]);
+        let mut history = history("");
+        record(&mut history, &dirs, &["b", "b/x"]);
+
+        let report = history.forget(Some("s"), &dirs.path("b"), false).unwrap();
+        assert_eq!(report.paths, [dirs.path("b")]);
+        assert_eq!(events(&history, "s"), 1);
+    }
+
+    #[test]
+    fn forget_in_a_child_reaches_the_entries_it_inherited() {
+        let dirs = TempDirs::new("forget-inherited", &["a", "secret", "c"]);
+        let mut history = history("");
+        record(&mut history, &dirs, &["a", "secret"]);
+        assert!(history.inherit("child", "s").unwrap());
+        history
+            .record("child", &dirs.path("c"), PathPolicy::Logical)
+            .unwrap();
+
+        let report = history
+            .forget(Some("child"), &dirs.path("secret"), false)
+            .unwrap();
+        assert_eq!(report.events, 1);
+        assert_eq!(
+            listed(&mut history, &HistoryView::session("child")),
+            [dirs.path("a")]
+        );
+        assert_eq!(events(&history, "s"), 1);
+    }
+
+    #[test]
+    fn prune_keeps_events_a_cursor_refers_to() {
+        let dirs = TempDirs::new("prune", &["a", "x", "y", "c"]);
+        let mut history = history("");
+        record(&mut history, &dirs, &["a", "x", "y", "c"]);
+        history.back(&HistoryView::session("s"), 1).unwrap();
+        fs::remove_dir(dirs.path("x")).unwrap();
+        fs::remove_dir(dirs.path("y")).unwrap();
+
+        let report = history.prune(Some("s"), true).unwrap();
+        assert_eq!(report.paths, [(dirs.path("x"), 1)]);
+        assert_eq!((report.events, report.kept), (1, 1));
+        assert_eq!(events(&history, "s"), 4);
+
+        history.prune(Some("s"), false).unwrap();
+        assert_eq!(events(&history, "s"), 3);
+    }
+
+    #[test]
+    fn forget_drops_the_interned_path() {
+        let dirs = TempDirs::new("forget-dirs", &["a", "secret"]);
+        let db = dirs.root.join("bd.sqlite3");
+        let mut history = History::with_store(Box::new(SqliteStore::open(&db).unwrap()));
+        record(&mut history, &dirs, &["a", "secret", "a"]);
+        history.forget(None, &dirs.path("secret"), false).unwrap();
+
+        let conn = history.store.as_sqlite().unwrap().connection();
+        let paths: Vec<String> = conn
+            .prepare("SELECT path FROM dirs")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(paths, [dirs.path("a")]);
+    }
+}
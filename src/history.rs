@@ -0,0 +1,1440 @@
+//! Per-session history: recording directory changes, moving the cursor, and cancel.
+
+use crate::db::{current_host, current_ts, db_path};
+use crate::ignore::matching_rule;
+use crate::paths::{
+    normalize_logical, resolve_ancestor, resolve_record_path, MatchMode, PathMatcher, PathPolicy,
+};
+use crate::store::{
+    EventFilter, EventRow, EventScan, LastMove, MemoryStore, NewEvent, ParentLink, SqliteStore,
+    Store,
+};
+use crate::{Config, Error};
+use clap::ValueEnum;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Cursor of a session whose current directory is not in history: the walk back starts
+/// at the newest event, as it does for a merged scope.
+pub(crate) const PAST_NEWEST: i64 = i64::MAX;
+
+/// Entry point to a history store. Every method runs in its own transaction.
+pub struct History {
+    pub(crate) store: Box<dyn Store>,
+    config: Config,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HistoryScope {
+    /// Only events recorded by this session.
+    Session,
+    /// Events recorded on this host by any session.
+    Host,
+    /// Events recorded by every session.
+    Global,
+}
+
+/// Which events `back`, `forward` and `list` walk. The cursor and cancel stack always
+/// belong to `session`; only the stream of candidate events changes with `scope`.
+/// With `unique`, each path counts once (at its occurrence nearest the cursor).
+pub struct HistoryView<'a> {
+    pub session: &'a str,
+    pub scope: HistoryScope,
+    pub unique: bool,
+}
+
+impl<'a> HistoryView<'a> {
+    /// The session's own history, one step per event.
+    pub fn session(session: &'a str) -> Self {
+        HistoryView {
+            session,
+            scope: HistoryScope::Session,
+            unique: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Back,
+    Forward,
+}
+
+impl Direction {
+    fn empty_message(self) -> &'static str {
+        match self {
+            Direction::Back => "no earlier directory",
+            Direction::Forward => "no later directory",
+        }
+    }
+
+    fn usage(self) -> &'static str {
+        match self {
+            Direction::Back => "usage: bd [N|c|ls]",
+            Direction::Forward => "usage: bd f [N|ls]",
+        }
+    }
+
+    fn signed(self, steps: u32) -> i64 {
+        match self {
+            Direction::Back => i64::from(steps),
+            Direction::Forward => -i64::from(steps),
+        }
+    }
+}
+
+pub struct HistoryEntry {
+    pub id: i64,
+    /// The path as recorded for display (logical or physical, per the path policy).
+    pub path: String,
+    /// The canonical path; falls back to `path` for events recorded before it existed.
+    pub real_path: String,
+    /// 1-based distance from the cursor.
+    pub steps: u32,
+    /// Unix time the directory change was recorded.
+    pub ts: i64,
+}
+
+/// A session's state, as listed by [`History::sessions`].
+pub struct SessionSummary {
+    pub session: String,
+    pub last_seen_at: i64,
+    /// Events recorded by this session.
+    pub events: usize,
+    /// The directory the cursor is on; `None` when it is past the newest event (the
+    /// current directory was not recorded) or its event no longer exists.
+    pub cursor_path: Option<String>,
+    /// Entries on the cancel stack.
+    pub undo_depth: usize,
+    pub paused: bool,
+    /// The session this one was started from, whose history it continues into.
+    pub parent_session: Option<String>,
+}
+
+/// A cursor move made by `back`, `forward`, `up` or `cancel`.
+pub struct Move {
+    pub from_id: i64,
+    pub to_id: i64,
+    /// Directory the caller should change into.
+    pub path: String,
+}
+
+impl History {
+    /// Opens the database at the default location (see [`db_path`]) with the user's
+    /// [`Config`].
+    pub fn open() -> Result<Self, Error> {
+        let config = Config::load()?;
+        Ok(Self::open_at(&db_path()?)?.with_config(config))
+    }
+
+    /// Opens the database at `path` with the built-in defaults; see [`History::with_config`].
+    pub fn open_at(path: &Path) -> Result<Self, Error> {
+        Ok(Self::with_store(Box::new(SqliteStore::open(path)?)))
+    }
+
+    /// Opens the in-memory history of an `--ephemeral` session, carried between
+    /// invocations in a snapshot under [`runtime_dir`]. Nothing is written to the database.
+    /// Uses the user's [`Config`].
+    pub fn ephemeral(session: &str) -> Result<Self, Error> {
+        let config = Config::load()?;
+        let name: String = session
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = runtime_dir()?.join(format!("ephemeral-{name}"));
+        Ok(Self::with_store(Box::new(MemoryStore::with_snapshot(&path)?)).with_config(config))
+    }
+
+    pub fn with_store(store: Box<dyn Store>) -> Self {
+        History {
+            store,
+            config: Config::default(),
+        }
+    }
+
+    /// Replaces the limits and retention periods this history applies.
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Maintenance before each command: replays records spooled while the database was
+    /// locked, then runs the periodic retention cleanup.
+    fn cleanup(&mut self, session: &str, now: i64) -> Result<(), Error> {
+        self.replay_spool()?;
+        self.store.cleanup(session, now, &self.config)
+    }
+
+    fn check_limit(&self, n: u32) -> Result<(), Error> {
+        let max = self.config.max_back.value;
+        if n > max {
+            return Err(Error::Usage(format!("max is {max}")));
+        }
+        Ok(())
+    }
+
+    /// Runs `f` inside a store transaction, rolling back if it fails.
+    pub(crate) fn atomic<T>(
+        &mut self,
+        f: impl FnOnce(&mut dyn Store) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        self.store.begin()?;
+        match f(self.store.as_mut()) {
+            Ok(value) => {
+                self.store.commit()?;
+                Ok(value)
+            }
+            Err(err) => {
+                self.store.rollback()?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Records that `session` is now in `pwd`. Repeats of the newest event are not
+    /// stored again; either way the cursor moves to the newest event and the cancel
+    /// stack is cleared.
+    ///
+    /// A directory excluded by the ignore rules, or any directory while the session is
+    /// paused, is not stored; the cursor moves past the newest event instead, so going
+    /// back from it starts with the last recorded one.
+    pub fn record(&mut self, session: &str, pwd: &str, policy: PathPolicy) -> Result<(), Error> {
+        if !Path::new(pwd).is_dir() {
+            return Err(Error::MissingDirectory(
+                "pwd is not a directory".to_string(),
+            ));
+        }
+        let (display, real_path) = resolve_record_path(pwd, policy)?;
+        let ignored = matching_rule(&self.config, &normalize_logical(pwd)?, &real_path)?
+            .is_some_and(|rule| rule.exclude);
+
+        let now = current_ts();
+        self.cleanup(session, now)?;
+        let keep = self.config.events_per_session.value as usize;
+        self.atomic(|store| {
+            let record = Record {
+                session,
+                display: &display,
+                real_path: &real_path,
+                ignored,
+                ts: now,
+            };
+            store_record(store, &record, keep)
+        })
+    }
+
+    /// Moves `n` steps back, or to the oldest existing directory if fewer remain.
+    pub fn back(&mut self, view: &HistoryView<'_>, n: u32) -> Result<Move, Error> {
+        self.step(view, n, Direction::Back)
+    }
+
+    /// Moves `n` steps forward again after going back.
+    pub fn forward(&mut self, view: &HistoryView<'_>, n: u32) -> Result<Move, Error> {
+        self.step(view, n, Direction::Forward)
+    }
+
+    fn step(
+        &mut self,
+        view: &HistoryView<'_>,
+        n: u32,
+        direction: Direction,
+    ) -> Result<Move, Error> {
+        if n == 0 {
+            return Err(Error::Usage(direction.usage().to_string()));
+        }
+        self.check_limit(n)?;
+
+        self.cleanup(view.session, current_ts())?;
+        self.atomic(|store| {
+            let cursor_id = resolve_cursor(store, view, direction.empty_message())?;
+
+            let mut target: Option<HistoryEntry> = None;
+            let mut furthest_existing: Option<HistoryEntry> = None;
+            walk_history(store, view, direction, cursor_id, |entry| {
+                if Path::new(&entry.path).is_dir() {
+                    if entry.steps >= n {
+                        target = Some(entry);
+                        return false;
+                    }
+                    furthest_existing = Some(entry);
+                }
+                true
+            })?;
+
+            let target = target
+                .or(furthest_existing)
+                .ok_or_else(|| Error::EmptyHistory(direction.empty_message().to_string()))?;
+            record_move(store, view.session, cursor_id, &target, direction)
+        })
+    }
+
+    /// Moves back to the newest existing directory matching `pattern`.
+    pub fn back_match(
+        &mut self,
+        view: &HistoryView<'_>,
+        pattern: &str,
+        mode: MatchMode,
+    ) -> Result<Move, Error> {
+        if pattern.is_empty() {
+            return Err(Error::Usage("usage: bd g PATTERN".to_string()));
+        }
+        let matcher = PathMatcher::new(pattern, mode)?;
+        let no_match = format!("no earlier directory matching {pattern}");
+
+        self.cleanup(view.session, current_ts())?;
+        self.atomic(|store| {
+            let cursor_id = resolve_cursor(store, view, &no_match)?;
+
+            let mut target: Option<HistoryEntry> = None;
+            walk_history(store, view, Direction::Back, cursor_id, |entry| {
+                let matched = matcher.is_match(&entry.path) || matcher.is_match(&entry.real_path);
+                if matched && Path::new(&entry.path).is_dir() {
+                    target = Some(entry);
+                    return false;
+                }
+                true
+            })?;
+            let target = target.ok_or(Error::EmptyHistory(no_match))?;
+            record_move(store, view.session, cursor_id, &target, Direction::Back)
+        })
+    }
+
+    /// Returns to where the session was `ago_secs` seconds ago: the newest existing
+    /// directory among its events (own or inherited) recorded at or before that moment.
+    /// The target does not depend on the cursor, so it is the same after `back` and
+    /// `forward` moves, and may lie forward of the cursor. Fails with
+    /// [`Error::EmptyHistory`] when the cursor is already there. Always uses this session's
+    /// history only.
+    pub fn back_ago(&mut self, session: &str, ago_secs: i64) -> Result<Move, Error> {
+        let ago = format_duration(ago_secs);
+        let no_match = format!("no directory recorded {ago} ago");
+
+        let now = current_ts();
+        self.cleanup(session, now)?;
+        let view = HistoryView::session(session);
+        self.atomic(|store| {
+            let cursor_id = resolve_cursor(store, &view, &no_match)?;
+            let cutoff = now.saturating_sub(ago_secs);
+
+            let mut target: Option<EventRow> = None;
+            scan_session(
+                store,
+                session,
+                true,
+                PAST_NEWEST,
+                Some(cutoff),
+                &mut |event| {
+                    if Path::new(&event.path).is_dir() {
+                        target = Some(event);
+                        return false;
+                    }
+                    true
+                },
+            )?;
+            let target = target.ok_or(Error::EmptyHistory(no_match))?;
+            if target.id == cursor_id {
+                return Err(Error::EmptyHistory(format!(
+                    "already where this session was {ago} ago"
+                )));
+            }
+
+            // Count the steps the way `back` or `forward` would, for the last move.
+            let direction = if target.id < cursor_id {
+                Direction::Back
+            } else {
+                Direction::Forward
+            };
+            let mut steps = 0;
+            walk_history(store, &view, direction, cursor_id, |entry| {
+                steps = entry.steps;
+                entry.id != target.id
+            })?;
+            let target = HistoryEntry {
+                id: target.id,
+                path: target.path,
+                real_path: target.real_path,
+                steps,
+                ts: target.ts,
+            };
+            record_move(store, session, cursor_id, &target, direction)
+        })
+    }
+
+    /// Returns up to `limit` existing entries in `direction`, nearest to the cursor first.
+    /// Their `steps` are the `N` that `back`/`forward` would take to reach them.
+    pub fn list(
+        &mut self,
+        view: &HistoryView<'_>,
+        direction: Direction,
+        limit: u32,
+    ) -> Result<Vec<HistoryEntry>, Error> {
+        if limit == 0 {
+            return Err(Error::Usage("usage: bd ls [N]".to_string()));
+        }
+        self.check_limit(limit)?;
+        let empty_message = match direction {
+            Direction::Back => "no history in this session",
+            Direction::Forward => "no later directory",
+        };
+
+        let now = current_ts();
+        self.cleanup(view.session, now)?;
+        let entries = self.atomic(|store| {
+            let cursor_id = resolve_cursor(store, view, "no history in this session")?;
+            store.touch_session(view.session, now)?;
+
+            let mut entries: Vec<HistoryEntry> = Vec::new();
+            walk_history(store, view, direction, cursor_id, |entry| {
+                if Path::new(&entry.path).is_dir() {
+                    entries.push(entry);
+                }
+                entries.len() < limit as usize
+            })?;
+            Ok(entries)
+        })?;
+
+        if entries.is_empty() {
+            return Err(Error::EmptyHistory(empty_message.to_string()));
+        }
+        Ok(entries)
+    }
+
+    /// Like [`History::list`] going back, but keeps entries whose directory no longer
+    /// exists so callers can show them. `limit` bounds the number of steps walked.
+    pub fn entries(
+        &mut self,
+        view: &HistoryView<'_>,
+        limit: u32,
+    ) -> Result<Vec<HistoryEntry>, Error> {
+        self.cleanup(view.session, current_ts())?;
+        self.atomic(|store| {
+            let cursor_id = resolve_cursor(store, view, "no history in this session")?;
+
+            let mut entries: Vec<HistoryEntry> = Vec::new();
+            walk_history(store, view, Direction::Back, cursor_id, |entry| {
+                entries.push(entry);
+                entries.len() < limit as usize
+            })?;
+            Ok(entries)
+        })
+    }
+
+    /// Moves the cursor straight to `entry`, typically one returned by
+    /// [`History::entries`], as if `back` had been called with its step count.
+    pub fn jump_to(&mut self, view: &HistoryView<'_>, entry: &HistoryEntry) -> Result<Move, Error> {
+        self.atomic(|store| {
+            let cursor_id = resolve_cursor(store, view, "no history in this session")?;
+            if store.event(entry.id)?.is_none() {
+                return Err(Error::EmptyHistory(
+                    "selected entry is no longer in history".to_string(),
+                ));
+            }
+            record_move(store, view.session, cursor_id, entry, Direction::Back)
+        })
+    }
+
+    /// Pops the newest move off the cancel stack and returns to where it started.
+    pub fn cancel(&mut self, session: &str) -> Result<Move, Error> {
+        let now = current_ts();
+        self.cleanup(session, now)?;
+        self.atomic(|store| {
+            let undo = store.last_undo(session)?.ok_or(Error::NothingToCancel)?;
+
+            let target_path = match store.event(undo.from_id)? {
+                Some(event) if Path::new(&event.path).is_dir() => event.path,
+                _ => return Err(Error::NothingToCancel),
+            };
+
+            store.set_cursor(session, undo.from_id, None, now)?;
+            store.remove_undo(undo.id)?;
+            Ok(Move {
+                from_id: undo.to_id,
+                to_id: undo.from_id,
+                path: target_path,
+            })
+        })
+    }
+
+    /// Stops `record` and `up` from storing directories for `session` until
+    /// [`History::resume`]. Existing history can still be walked and listed.
+    pub fn pause(&mut self, session: &str) -> Result<(), Error> {
+        self.set_paused(session, true)
+    }
+
+    pub fn resume(&mut self, session: &str) -> Result<(), Error> {
+        self.set_paused(session, false)
+    }
+
+    pub fn is_paused(&self, session: &str) -> Result<bool, Error> {
+        self.store.paused(session)
+    }
+
+    /// Links `session` to the history of `parent` as it stands now, so a shell started from
+    /// another one can go back at once. Walking back through `session` continues into the
+    /// newest `max_back` entries of `parent`'s history (its own events, and from there the
+    /// ones it inherited in turn); nothing is copied, so the events keep their session and
+    /// host. Only a session with no cursor and no events is linked; returns whether it was.
+    pub fn inherit(&mut self, session: &str, parent: &str) -> Result<bool, Error> {
+        if session == parent {
+            return Ok(false);
+        }
+        let now = current_ts();
+        let max_back = self.config.max_back.value as usize;
+        self.atomic(|store| {
+            if store.cursor(session)?.is_some() || store.latest_event(session)?.is_some() {
+                return Ok(false);
+            }
+            let mut ids = Vec::new();
+            scan_session(store, parent, true, PAST_NEWEST, None, &mut |event| {
+                ids.push(event.id);
+                ids.len() < max_back
+            })?;
+            let (Some(&max_id), Some(&min_id)) = (ids.first(), ids.last()) else {
+                return Ok(false);
+            };
+            let link = ParentLink {
+                session: parent.to_string(),
+                min_id,
+                max_id,
+            };
+            store.set_parent(session, &link, now)?;
+            Ok(true)
+        })
+    }
+
+    /// Every session that has a cursor, most recently seen first. Records still waiting in
+    /// the spool are stored first so they are counted.
+    pub fn sessions(&mut self) -> Result<Vec<SessionSummary>, Error> {
+        self.replay_spool()?;
+        let store = &*self.store;
+        let mut summaries = Vec::new();
+        for row in store.sessions()? {
+            let mut events = 0;
+            store.scan_events(
+                &EventScan {
+                    filter: EventFilter::Session(&row.session),
+                    backward: false,
+                    from_id: 0,
+                    max_ts: None,
+                },
+                &mut |_| {
+                    events += 1;
+                    true
+                },
+            )?;
+            summaries.push(SessionSummary {
+                cursor_path: store.event(row.cursor_id)?.map(|event| event.path),
+                undo_depth: store.undo_moves(&row.session)?.len(),
+                parent_session: store.parent(&row.session)?.map(|link| link.session),
+                session: row.session,
+                last_seen_at: row.last_seen_at,
+                events,
+                paused: row.paused,
+            });
+        }
+        summaries.sort_by_key(|summary| Reverse(summary.last_seen_at));
+        Ok(summaries)
+    }
+
+    fn set_paused(&mut self, session: &str, paused: bool) -> Result<(), Error> {
+        let now = current_ts();
+        self.atomic(|store| store.set_paused(session, paused, now))
+    }
+
+    /// Climbs to an ancestor of `pwd`, either `target` levels up or the nearest ancestor
+    /// whose name is (or else starts with) `target`. The ancestor is appended to history
+    /// and the move is pushed onto the cancel stack, so `cancel` returns to `pwd`. Both
+    /// directories are recorded under `policy`, as `record` would store them.
+    ///
+    /// Like `record`, nothing is stored for a directory excluded by the ignore rules or
+    /// while the session is paused; the cursor moves past the newest event instead. The
+    /// move is still pushed when `pwd` was recorded, but `cancel` cannot return to a `pwd`
+    /// that was not.
+    pub fn up(
+        &mut self,
+        session: &str,
+        pwd: &str,
+        target: &str,
+        policy: PathPolicy,
+    ) -> Result<Move, Error> {
+        if !Path::new(pwd).is_dir() {
+            return Err(Error::MissingDirectory(
+                "pwd is not a directory".to_string(),
+            ));
+        }
+        let pwd = normalize_logical(pwd)?;
+        let ancestor = resolve_ancestor(Path::new(&pwd), target)?;
+        let ancestor = ancestor.to_string_lossy().into_owned();
+        if !Path::new(&ancestor).is_dir() {
+            return Err(Error::MissingDirectory(format!(
+                "{ancestor} is not a directory"
+            )));
+        }
+        let (pwd_display, pwd_real) = resolve_record_path(&pwd, policy)?;
+        let (ancestor_display, ancestor_real) = resolve_record_path(&ancestor, policy)?;
+        let excluded = |logical: &str, real_path: &str| -> Result<bool, Error> {
+            Ok(matching_rule(&self.config, logical, real_path)?.is_some_and(|rule| rule.exclude))
+        };
+        let pwd_ignored = excluded(&pwd, &pwd_real)?;
+        let ancestor_ignored = excluded(&ancestor, &ancestor_real)?;
+
+        let now = current_ts();
+        self.cleanup(session, now)?;
+        let keep = self.config.events_per_session.value as usize;
+        self.atomic(|store| {
+            let paused = store.paused(session)?;
+            let cursor_id = match latest_session_event(store, session)? {
+                Some(_) => resolve_cursor(
+                    store,
+                    &HistoryView::session(session),
+                    "no history in this session",
+                )?,
+                None if paused || pwd_ignored => PAST_NEWEST,
+                None => insert_event(store, session, &pwd_display, &pwd_real, now)?,
+            };
+            let target_id = if paused || ancestor_ignored {
+                PAST_NEWEST
+            } else {
+                insert_event(store, session, &ancestor_display, &ancestor_real, now)?
+            };
+            push_move(store, session, cursor_id, target_id, 0, now)?;
+            store.rotate_events(session, keep)?;
+
+            Ok(Move {
+                from_id: cursor_id,
+                to_id: target_id,
+                path: ancestor,
+            })
+        })
+    }
+}
+
+/// Where `--ephemeral` sessions keep their snapshot: `$XDG_RUNTIME_DIR/back-directory`, or
+/// `back-directory-<uid>` in the system temp directory when there is no runtime directory.
+/// The temp directory is shared, so that fallback is created private and refused when it
+/// belongs to someone else or others can read it.
+pub fn runtime_dir() -> Result<PathBuf, Error> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir).join("back-directory"));
+    }
+
+    // SAFETY: geteuid cannot fail.
+    let uid = unsafe { libc::geteuid() };
+    let dir = env::temp_dir().join(format!("back-directory-{uid}"));
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(err) => return Err(Error::Io(format!("{}: {err}", dir.display()))),
+    }
+    let meta = std::fs::symlink_metadata(&dir)?;
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+        return Err(Error::Other(format!(
+            "{} is not a private directory of this user",
+            dir.display()
+        )));
+    }
+    Ok(dir)
+}
+
+/// Parses durations such as `90s`, `15m`, `2h` or `1d` into seconds.
+pub fn parse_duration(value: &str) -> Result<i64, Error> {
+    let invalid = || {
+        Error::Usage(format!(
+            "invalid duration: {value} (use e.g. 30s, 15m, 2h, 1d)"
+        ))
+    };
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    if amount == 0 {
+        return Err(invalid());
+    }
+    amount.checked_mul(unit_secs).ok_or_else(invalid)
+}
+
+/// Inverse of [`parse_duration`], using the largest unit that divides evenly.
+pub(crate) fn format_duration(secs: i64) -> String {
+    for (unit, unit_secs) in [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60)] {
+        if secs >= unit_secs && secs % unit_secs == 0 {
+            return format!("{}{unit}", secs / unit_secs);
+        }
+    }
+    format!("{secs}s")
+}
+
+/// A directory change ready to be stored, with the ignore rules already applied.
+#[derive(Clone, Copy)]
+pub(crate) struct Record<'a> {
+    pub session: &'a str,
+    pub display: &'a str,
+    pub real_path: &'a str,
+    pub ignored: bool,
+    pub ts: i64,
+}
+
+/// The storing half of [`History::record`], shared with spool replay.
+pub(crate) fn store_record(
+    store: &mut dyn Store,
+    record: &Record<'_>,
+    keep: usize,
+) -> Result<(), Error> {
+    let Record {
+        session,
+        display,
+        real_path,
+        ignored,
+        ts,
+    } = *record;
+    if ignored || store.paused(session)? {
+        store.set_cursor(session, PAST_NEWEST, None, ts)?;
+        return store.clear_undo(session);
+    }
+
+    let latest = store.latest_event(session)?;
+    if latest.is_none() {
+        // A new session's first directory is usually the one its parent was last in. The
+        // session gets its own event for it, and the parent's is left out of the link so
+        // walking back does not visit the same directory twice.
+        if let (Some(link), Some(inherited)) = (
+            store.parent(session)?,
+            latest_session_event(store, session)?,
+        ) {
+            if store.dir_id(real_path)? == Some(inherited.real_dir_id) {
+                let link = ParentLink {
+                    max_id: inherited.id - 1,
+                    ..link
+                };
+                store.set_parent(session, &link, ts)?;
+            }
+        }
+    }
+    let latest_id = match latest {
+        Some(event) if store.dir_id(real_path)? == Some(event.real_dir_id) => event.id,
+        _ => insert_event(store, session, display, real_path, ts)?,
+    };
+
+    store.set_cursor(session, latest_id, None, ts)?;
+    store.clear_undo(session)?;
+    store.rotate_events(session, keep)
+}
+
+fn insert_event(
+    store: &mut dyn Store,
+    session: &str,
+    path: &str,
+    real_path: &str,
+    now: i64,
+) -> Result<i64, Error> {
+    store.insert_event(&NewEvent {
+        session,
+        path,
+        real_path,
+        ts: now,
+        host: &current_host(),
+    })
+}
+
+/// Returns the session cursor, falling back to the newest event when the session has
+/// no cursor yet or its cursor event was rotated away.
+///
+/// With a merged scope the cursor may point at another session's event, so any
+/// existing event id is accepted; a session without events starts past the newest one,
+/// as does a session whose current directory was ignored by `record`.
+fn resolve_cursor(
+    store: &dyn Store,
+    view: &HistoryView<'_>,
+    empty_message: &str,
+) -> Result<i64, Error> {
+    let latest_id = latest_session_event(store, view.session)?.map(|event| event.id);
+    let latest_id = match view.scope {
+        HistoryScope::Session => latest_id,
+        HistoryScope::Host | HistoryScope::Global => latest_id.or(Some(PAST_NEWEST)),
+    };
+    let empty = || Error::EmptyHistory(empty_message.to_string());
+
+    let cursor_id = match store.cursor(view.session)? {
+        Some(id) => id,
+        None => latest_id.ok_or_else(empty)?,
+    };
+    if cursor_id == PAST_NEWEST {
+        return Ok(cursor_id);
+    }
+    if store.event(cursor_id)?.is_none() {
+        return latest_id.ok_or_else(empty);
+    }
+    Ok(cursor_id)
+}
+
+/// Streams events away from `cursor_id`, calling `visit` with each entry until it
+/// returns `false`.
+///
+/// Merged scopes interleave several sessions, so consecutive repeats of the same
+/// directory (including the cursor's own) are collapsed into one step. Unique views go
+/// further and skip every directory already visited by the walk, as well as the
+/// cursor's. Directories are compared by the interned id of their canonical path.
+fn walk_history<F>(
+    store: &dyn Store,
+    view: &HistoryView<'_>,
+    direction: Direction,
+    cursor_id: i64,
+    mut visit: F,
+) -> Result<(), Error>
+where
+    F: FnMut(HistoryEntry) -> bool,
+{
+    let host = current_host();
+    let filter = match view.scope {
+        HistoryScope::Session => None,
+        HistoryScope::Host => Some(EventFilter::Host(&host)),
+        HistoryScope::Global => Some(EventFilter::All),
+    };
+    let backward = direction == Direction::Back;
+
+    let collapse_repeats = view.unique || view.scope != HistoryScope::Session;
+    let mut previous: Option<i64> = if collapse_repeats {
+        store.event(cursor_id)?.map(|event| event.real_dir_id)
+    } else {
+        None
+    };
+    let mut seen: HashSet<i64> = HashSet::new();
+    if view.unique {
+        seen.extend(previous);
+    }
+
+    let mut steps: u32 = 0;
+    let mut visit_event = |event: EventRow| {
+        if view.unique && !seen.insert(event.real_dir_id) {
+            return true;
+        }
+        if collapse_repeats {
+            if previous == Some(event.real_dir_id) {
+                return true;
+            }
+            previous = Some(event.real_dir_id);
+        }
+        steps += 1;
+        visit(HistoryEntry {
+            id: event.id,
+            path: event.path,
+            real_path: event.real_path,
+            steps,
+            ts: event.ts,
+        })
+    };
+    match filter {
+        Some(filter) => store.scan_events(
+            &EventScan {
+                filter,
+                backward,
+                from_id: cursor_id,
+                max_ts: None,
+            },
+            &mut visit_event,
+        ),
+        None => scan_session(
+            store,
+            view.session,
+            backward,
+            cursor_id,
+            None,
+            &mut visit_event,
+        ),
+    }
+}
+
+/// Scans the events of `session` away from `from_id` (exclusive) like
+/// [`Store::scan_events`], continuing into the history it inherited: the events of its
+/// parent session up to the link, then those of the parent's parent, and so on. Every
+/// session's own events are newer than the ones it inherited, so the result is in id order.
+/// Each link only reaches the range of ids it was made with, narrowed by the links before
+/// it. With `max_ts`, events recorded after it are left out.
+pub(crate) fn scan_session(
+    store: &dyn Store,
+    session: &str,
+    backward: bool,
+    from_id: i64,
+    max_ts: Option<i64>,
+    visit: &mut dyn FnMut(EventRow) -> bool,
+) -> Result<(), Error> {
+    // Each segment is a session and the oldest and newest of its events that are included.
+    let mut segments = vec![(session.to_string(), 0, PAST_NEWEST)];
+    let mut current = session.to_string();
+    while let Some(link) = store.parent(&current)? {
+        // A link back into the chain would walk in circles.
+        if segments.iter().any(|(seen, _, _)| *seen == link.session) {
+            break;
+        }
+        let (_, min_id, max_id) = segments[segments.len() - 1];
+        segments.push((
+            link.session.clone(),
+            link.min_id.max(min_id),
+            link.max_id.min(max_id),
+        ));
+        current = link.session;
+    }
+    if !backward {
+        segments.reverse();
+    }
+
+    let mut stopped = false;
+    for (segment, min_id, max_id) in &segments {
+        let scan = EventScan {
+            filter: EventFilter::Session(segment),
+            backward,
+            from_id: if backward {
+                from_id.min(max_id.saturating_add(1))
+            } else {
+                from_id.max(min_id - 1)
+            },
+            max_ts,
+        };
+        store.scan_events(&scan, &mut |event| {
+            if event.id > *max_id || event.id < *min_id {
+                return false;
+            }
+            if !visit(event) {
+                stopped = true;
+                return false;
+            }
+            true
+        })?;
+        if stopped {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The newest event a session-scope walk back from past the newest event would visit
+/// first: the session's own newest event, or else the newest one it inherited.
+fn latest_session_event(store: &dyn Store, session: &str) -> Result<Option<EventRow>, Error> {
+    let mut latest = None;
+    scan_session(store, session, true, PAST_NEWEST, None, &mut |event| {
+        latest = Some(event);
+        false
+    })?;
+    Ok(latest)
+}
+
+/// Moves the session cursor to `target`, `target.steps` away in `direction`, and returns
+/// the resulting move.
+fn record_move(
+    store: &mut dyn Store,
+    session: &str,
+    cursor_id: i64,
+    target: &HistoryEntry,
+    direction: Direction,
+) -> Result<Move, Error> {
+    push_move(
+        store,
+        session,
+        cursor_id,
+        target.id,
+        direction.signed(target.steps),
+        current_ts(),
+    )?;
+    Ok(Move {
+        from_id: cursor_id,
+        to_id: target.id,
+        path: target.path.clone(),
+    })
+}
+
+/// Moves the session cursor and pushes the move onto the cancel stack. A move away from
+/// a directory that was never recorded (the cursor is past the newest event) is not
+/// pushed: there is no event to return to, and storing the path would defeat the ignore
+/// rules or the pause that kept it out.
+fn push_move(
+    store: &mut dyn Store,
+    session: &str,
+    from_id: i64,
+    to_id: i64,
+    delta: i64,
+    now: i64,
+) -> Result<(), Error> {
+    let last_move = LastMove {
+        delta,
+        from_id,
+        to_id,
+    };
+    store.set_cursor(session, to_id, Some(&last_move), now)?;
+    if from_id == PAST_NEWEST {
+        return Ok(());
+    }
+    store.push_undo(session, from_id, to_id, now)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Real directories under a fresh temporary root, removed when dropped.
+    pub(crate) struct TempDirs {
+        pub(crate) root: PathBuf,
+    }
+
+    impl TempDirs {
+        pub(crate) fn new(name: &str, dirs: &[&str]) -> Self {
+            let root = env::temp_dir().join(format!("bd-test-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            for dir in dirs {
+                fs::create_dir_all(root.join(dir)).unwrap();
+            }
+            TempDirs { root }
+        }
+
+        pub(crate) fn path(&self, dir: &str) -> String {
+            self.root.join(dir).to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for TempDirs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    pub(crate) fn history(config: &str) -> History {
+        let config = Config::parse(config, Path::new("config.toml")).unwrap();
+        History::with_store(Box::new(MemoryStore::new())).with_config(config)
+    }
+
+    pub(crate) fn record(history: &mut History, dirs: &TempDirs, names: &[&str]) {
+        for name in names {
+            history
+                .record("s", &dirs.path(name), PathPolicy::Logical)
+                .unwrap();
+        }
+    }
+
+    pub(crate) fn listed(history: &mut History, view: &HistoryView<'_>) -> Vec<String> {
+        let limit = history.config().default_list.value;
+        history
+            .list(view, Direction::Back, limit)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect()
+    }
+
+    pub(crate) fn events(history: &History, session: &str) -> usize {
+        let scan = EventScan {
+            filter: EventFilter::Session(session),
+            backward: false,
+            from_id: 0,
+            max_ts: None,
+        };
+        let mut count = 0;
+        history
+            .store
+            .scan_events(&scan, &mut |_| {
+                count += 1;
+                true
+            })
+            .unwrap();
+        count
+    }
+
+    #[test]
+    fn record_skips_repeats_of_the_newest_directory() {
+        let dirs = TempDirs::new("repeats", &["a", "b"]);
+        let mut history = history("");
+        record(&mut history, &dirs, &["a", "a", "b", "b"]);
+
+        assert_eq!(events(&history, "s"), 2);
+        assert_eq!(
+            listed(&mut history, &HistoryView::session("s")),
+            [dirs.path("a")]
+        );
+    }
+
+    #[test]
+    fn back_and_forward_move_the_cursor() {
+        let dirs = TempDirs::new("steps", &["a", "b", "c"]);
+        let mut history = history("");
+        let view = HistoryView::session("s");
+        record(&mut history, &dirs, &["a", "b", "c"]);
+
+        assert_eq!(history.back(&view, 2).unwrap().path, dirs.path("a"));
+        assert_eq!(history.forward(&view, 1).unwrap().path, dirs.path("b"));
+        // Past the oldest entry, back stops at the oldest existing directory.
+        assert_eq!(history.back(&view, 5).unwrap().path, dirs.path("a"));
+        assert!(matches!(
+            history.back(&view, 1),
+            Err(Error::EmptyHistory(_))
+        ));
+    }
+
+    #[test]
+    fn back_skips_directories_that_no_longer_exist() {
+        let dirs = TempDirs::new("missing", &["a", "b", "c"]);
+        let mut history = history("");
+        record(&mut history, &dirs, &["a", "b", "c"]);
+        fs::remove_dir(dirs.path("b")).unwrap();
+
+        let view = HistoryView::session("s");
+        assert_eq!(history.back(&view, 1).unwrap().path, dirs.path("a"));
+    }
+
+    #[test]
+    fn back_ago_goes_where_the_session_was_at_that_time() {
+        let dirs = TempDirs::new("ago", &["a", "b", "c"]);
+        let mut history = history("");
+        let now = current_ts();
+        for (name, ago) in [("a", 3600), ("b", 1800), ("c", 60)] {
+            let path = dirs.path(name);
+            history
+                .store
+                .insert_event(&NewEvent {
+                    session: "s",
+                    path: &path,
+                    real_path: &path,
+                    ts: now - ago,
+                    host: "h",
+                })
+                .unwrap();
+        }
+
+        assert_eq!(history.back_ago("s", 2700).unwrap().path, dirs.path("a"));
+        // Already there.
+        assert!(matches!(
+            history.back_ago("s", 2700),
+            Err(Error::EmptyHistory(_))
+        ));
+        // The target is found by time, not by walking back from the cursor.
+        assert_eq!(history.back_ago("s", 900).unwrap().path, dirs.path("b"));
+        assert!(matches!(
+            history.back_ago("s", 7200),
+            Err(Error::EmptyHistory(_))
+        ));
+        assert_eq!(history.cancel("s").unwrap().path, dirs.path("a"));
+    }
+
+    #[test]
+    fn back_in_an_empty_session_is_empty_history() {
+        let mut history = history("");
+        assert!(matches!(
+            history.back(&HistoryView::session("s"), 1),
+            Err(Error::EmptyHistory(_))
+        ));
+    }
+
+    #[test]
+    fn back_beyond_max_back_is_a_usage_error() {
+        let mut history = history("max_back = 3\ndefault_list = 3\n");
+        assert!(matches!(
+            history.back(&HistoryView::session("s"), 4),
+            Err(Error::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn cancel_undoes_moves_newest_first() {
+        let dirs = TempDirs::new("cancel", &["a", "b", "c"]);
+        let mut history = history("");
+        let view = HistoryView::session("s");
+        record(&mut history, &dirs, &["a", "b", "c"]);
+        history.back(&view, 1).unwrap();
+        history.back(&view, 1).unwrap();
+
+        assert_eq!(history.cancel("s").unwrap().path, dirs.path("b"));
+        assert_eq!(history.cancel("s").unwrap().path, dirs.path("c"));
+        assert!(matches!(history.cancel("s"), Err(Error::NothingToCancel)));
+    }
+
+    #[test]
+    fn record_clears_the_cancel_stack() {
+        let dirs = TempDirs::new("clear", &["a", "b", "c"]);
+        let mut history = history("");
+        record(&mut history, &dirs, &["a", "b"]);
+        history.back(&HistoryView::session("s"), 1).unwrap();
+        record(&mut history, &dirs, &["c"]);
+
+        assert!(matches!(history.cancel("s"), Err(Error::NothingToCancel)));
+    }
+
+    #[test]
+    fn rotation_keeps_the_newest_events() {
+        let dirs = TempDirs::new("rotation", &["a", "b", "c", "d", "e"]);
+        let mut history = history("events_per_session = 3\n");
+        record(&mut history, &dirs, &["a", "b", "c", "d", "e"]);
+
+        assert_eq!(events(&history, "s"), 3);
+        assert_eq!(
+            listed(&mut history, &HistoryView::session("s")),
+            [dirs.path("d"), dirs.path("c")]
+        );
+    }
+
+    #[test]
+    fn excluded_directories_are_not_recorded() {
+        let dirs = TempDirs::new("exclude", &["a", "skip"]);
+        let mut history = history("exclude = [\"skip\"]\n");
+        let view = HistoryView::session("s");
+        record(&mut history, &dirs, &["a", "skip"]);
+
+        assert_eq!(events(&history, "s"), 1);
+        assert_eq!(history.back(&view, 1).unwrap().path, dirs.path("a"));
+        // The excluded directory is not in history, so there is nothing to return to.
+        assert!(matches!(history.cancel("s"), Err(Error::NothingToCancel)));
+    }
+
+    #[test]
+    fn a_paused_session_records_nothing() {
+        let dirs = TempDirs::new("pause", &["a", "b", "c"]);
+        let mut history = history("");
+        record(&mut history, &dirs, &["a"]);
+        history.pause("s").unwrap();
+        record(&mut history, &dirs, &["b"]);
+        history.resume("s").unwrap();
+        record(&mut history, &dirs, &["c"]);
+
+        assert!(!history.is_paused("s").unwrap());
+        assert_eq!(
+            listed(&mut history, &HistoryView::session("s")),
+            [dirs.path("a")]
+        );
+    }
+
+    #[test]
+    fn sessions_summarise_each_session() {
+        let dirs = TempDirs::new("sessions", &["a", "b"]);
+        let mut history = history("");
+        record(&mut history, &dirs, &["a", "b"]);
+        history.back(&HistoryView::session("s"), 1).unwrap();
+        history
+            .record("t", &dirs.path("a"), PathPolicy::Logical)
+            .unwrap();
+        history.pause("t").unwrap();
+
+        let sessions = history.sessions().unwrap();
+        let summary = |name: &str| sessions.iter().find(|s| s.session == name).unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(summary("s").events, 2);
+        assert_eq!(
+            summary("s").cursor_path.as_deref(),
+            Some(dirs.path("a").as_str())
+        );
+        assert_eq!(summary("s").undo_depth, 1);
+        assert!(!summary("s").paused);
+        assert_eq!(summary("t").events, 1);
+        assert!(summary("t").paused);
+    }
+
+    #[test]
+    fn inherited_history_continues_into_the_parent() {
+        let dirs = TempDirs::new("inherit", &["a", "b", "c"]);
+        let mut history = history("");
+        record(&mut history, &dirs, &["a", "b"]);
+        assert!(history.inherit("child", "s").unwrap());
+        history
+            .record("child", &dirs.path("c"), PathPolicy::Logical)
+            .unwrap();
+
+        // The child stores only its own event and walks on into the parent's.
+        assert_eq!(events(&history, "child"), 1);
+        assert_eq!(
+            listed(&mut history, &HistoryView::session("child")),
+            [dirs.path("b"), dirs.path("a")]
+        );
+        // Only a session without history of its own is linked.
+        assert!(!history.inherit("child", "s").unwrap());
+    }
+
+    #[test]
+    fn a_child_starting_where_its_parent_is_records_its_own_event() {
+        let dirs = TempDirs::new("inherit-first", &["a", "b"]);
+        let mut history = history("");
+        record(&mut history, &dirs, &["a", "b"]);
+        assert!(history.inherit("child", "s").unwrap());
+        history
+            .record("child", &dirs.path("b"), PathPolicy::Logical)
+            .unwrap();
+
+        assert_eq!(events(&history, "child"), 1);
+        // The parent's `b` is not visited again.
+        assert_eq!(
+            listed(&mut history, &HistoryView::session("child")),
+            [dirs.path("a")]
+        );
+        // The cursor is on the child's own event, so the next record moves on from it.
+        history
+            .record("child", &dirs.path("a"), PathPolicy::Logical)
+            .unwrap();
+        assert_eq!(events(&history, "child"), 2);
+    }
+
+    #[test]
+    fn a_child_inherits_only_the_newest_max_back_entries() {
+        let dirs = TempDirs::new("inherit-cap", &["a", "b", "c", "d", "e"]);
+        let mut history = history("max_back = 3\ndefault_list = 3\n");
+        record(&mut history, &dirs, &["a", "b", "c", "d"]);
+        assert!(history.inherit("child", "s").unwrap());
+        history
+            .record("child", &dirs.path("e"), PathPolicy::Logical)
+            .unwrap();
+        // Later records of the parent are not inherited either.
+        record(&mut history, &dirs, &["a"]);
+
+        assert_eq!(
+            listed(&mut history, &HistoryView::session("child")),
+            [dirs.path("d"), dirs.path("c"), dirs.path("b")]
+        );
+        assert_eq!(
+            history
+                .back(&HistoryView::session("child"), 3)
+                .unwrap()
+                .path,
+            dirs.path("b")
+        );
+        assert!(matches!(
+            history.back(&HistoryView::session("child"), 1),
+            Err(Error::EmptyHistory(_))
+        ));
+    }
+
+    #[test]
+    fn up_does_not_record_excluded_directories() {
+        let dirs = TempDirs::new("up-exclude", &["top/keep", "work/build"]);
+        let config = format!(
+            "exclude = [\"top\", \"build\"]\ninclude = [\"{}\"]\n",
+            dirs.path("top/keep")
+        );
+        let mut history = history(&config);
+
+        // Climbing out of an excluded directory leaves nothing to cancel back to.
+        record(&mut history, &dirs, &["work", "work/build"]);
+        let step = history
+            .up("s", &dirs.path("work/build"), "1", PathPolicy::Logical)
+            .unwrap();
+        assert_eq!(step.path, dirs.path("work"));
+        assert!(matches!(history.cancel("s"), Err(Error::NothingToCancel)));
+
+        // Climbing into one stores nothing, but cancel still returns.
+        record(&mut history, &dirs, &["top/keep"]);
+        let before = events(&history, "s");
+        let step = history
+            .up("s", &dirs.path("top/keep"), "1", PathPolicy::Logical)
+            .unwrap();
+        assert_eq!(step.path, dirs.path("top"));
+        assert_eq!(events(&history, "s"), before);
+        assert_eq!(history.cancel("s").unwrap().path, dirs.path("top/keep"));
+    }
+
+    #[test]
+    fn up_while_paused_stores_nothing_but_can_be_cancelled() {
+        let dirs = TempDirs::new("up-paused", &["a/b"]);
+        let mut history = history("");
+        record(&mut history, &dirs, &["a/b"]);
+        history.pause("s").unwrap();
+        let step = history
+            .up("s", &dirs.path("a/b"), "1", PathPolicy::Logical)
+            .unwrap();
+
+        assert_eq!(step.path, dirs.path("a"));
+        assert_eq!(events(&history, "s"), 1);
+        assert_eq!(history.cancel("s").unwrap().path, dirs.path("a/b"));
+    }
+
+    #[test]
+    fn up_can_be_cancelled() {
+        let dirs = TempDirs::new("up", &["a/b/c"]);
+        let mut history = history("");
+        record(&mut history, &dirs, &["a/b/c"]);
+        let step = history
+            .up("s", &dirs.path("a/b/c"), "2", PathPolicy::Logical)
+            .unwrap();
+
+        assert_eq!(step.path, dirs.path("a"));
+        assert_eq!(history.cancel("s").unwrap().path, dirs.path("a/b/c"));
+    }
+
+    #[test]
+    fn a_snapshot_carries_the_session_between_invocations() {
+        let dirs = TempDirs::new("snapshot", &["a", "b"]);
+        let snapshot = dirs.root.join("snapshot");
+        let open = || History::with_store(Box::new(MemoryStore::with_snapshot(&snapshot).unwrap()));
+        let view = HistoryView::session("s");
+        record(&mut open(), &dirs, &["a", "b"]);
+        open().back(&view, 1).unwrap();
+
+        let mut history = open();
+        assert_eq!(history.cancel("s").unwrap().path, dirs.path("b"));
+        assert_eq!(events(&history, "s"), 2);
+    }
+
+    #[test]
+    fn parse_duration_accepts_each_unit() {
+        assert_eq!(parse_duration("90s").unwrap(), 90);
+        assert_eq!(parse_duration("15m").unwrap(), 15 * 60);
+        assert_eq!(parse_duration("2h").unwrap(), 2 * 60 * 60);
+        assert_eq!(parse_duration("1d").unwrap(), 24 * 60 * 60);
+    }
+
+    #[test]
+    fn parse_duration_rejects_malformed_values() {
+        for value in [
+            "",
+            "10",
+            "m",
+            "0s",
+            "5w",
+            "1.5h",
+            "-1d",
+            "99999999999999999999d",
+        ] {
+            assert!(
+                matches!(parse_duration(value), Err(Error::Usage(_))),
+                "{value:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn format_duration_round_trips() {
+        for value in ["45s", "90m", "36h", "180d"] {
+            assert_eq!(format_duration(parse_duration(value).unwrap()), value);
+        }
+    }
+
+    #[test]
+    fn up_records_under_the_path_policy() {
+        let dirs = TempDirs::new("up-policy", &["real/sub"]);
+        std::os::unix::fs::symlink(dirs.path("real"), dirs.path("link")).unwrap();
+        let mut history = history("");
+        let step = history
+            .up("s", &dirs.path("link/sub"), "1", PathPolicy::Physical)
+            .unwrap();
+
+        // The shell moves along the logical path; history stores the resolved one.
+        assert_eq!(step.path, dirs.path("link"));
+        assert_eq!(
+            history.back(&HistoryView::session("s"), 1).unwrap().path,
+            dirs.path("real/sub")
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! Include/exclude rules deciding which directories `record` leaves out of history.
+//!
+//! Rules come from `exclude` and then `include` in the config file, followed by the
+//! `.bdignore` files of the directory and its ancestors, outermost first. The last rule
+//! that matches wins, so a nearer `.bdignore` can re-include what the config excludes.
+//!
+//! A pattern without a `/` is matched against directory names; one with a `/` against
+//! whole paths. Either way a rule that matches a directory also covers everything below
+//! it, so `node_modules` keeps out every directory inside a `node_modules`.
+
+use crate::paths::normalize_logical;
+use crate::{Config, Error};
+use glob::{MatchOptions, Pattern};
+use std::path::{Path, PathBuf};
+
+const IGNORE_FILE: &str = ".bdignore";
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// The rule that decided whether a directory is recorded.
+pub struct IgnoreRule {
+    /// The pattern as written, with a leading `!` for `.bdignore` includes.
+    pub pattern: String,
+    /// Whether the rule keeps matching directories out of history.
+    pub exclude: bool,
+    /// Where the rule was read: the config file and key, or `.bdignore` path and line.
+    pub origin: String,
+}
+
+pub(crate) struct Rule {
+    about: IgnoreRule,
+    glob: Pattern,
+    /// Matched against whole paths rather than single directory names.
+    anchored: bool,
+    /// Directory holding the `.bdignore`; only it and its descendants can match.
+    base: Option<PathBuf>,
+}
+
+impl Rule {
+    fn matches(&self, path: &Path) -> bool {
+        for dir in path.ancestors() {
+            if let Some(base) = &self.base {
+                if !dir.starts_with(base) {
+                    break;
+                }
+            }
+            let hit = if self.anchored {
+                self.glob.matches_path_with(dir, MATCH_OPTIONS)
+            } else {
+                dir.file_name().is_some_and(|name| {
+                    self.glob
+                        .matches_with(&name.to_string_lossy(), MATCH_OPTIONS)
+                })
+            };
+            if hit {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Compiles the `exclude` and `include` patterns of `config`. Patterns with a `/` must be
+/// absolute or start with `~/`.
+pub(crate) fn config_rules(config: &Config) -> Result<Vec<Rule>, String> {
+    let file = config_file(config);
+    let mut rules = Vec::new();
+    for (key, exclude, patterns) in [
+        ("exclude", true, &config.exclude.value),
+        ("include", false, &config.include.value),
+    ] {
+        for pattern in patterns {
+            let invalid = |msg: &str| format!("{key}: {pattern:?}: {msg}");
+            let text = pattern.trim_end_matches('/');
+            let (glob, anchored) = if !text.contains('/') {
+                (text.to_string(), false)
+            } else if let Some(rest) = text.strip_prefix("~/") {
+                let home = std::env::var("HOME").map_err(|_| invalid("HOME not set"))?;
+                (
+                    format!("{}/{rest}", Pattern::escape(home.trim_end_matches('/'))),
+                    true,
+                )
+            } else if text.starts_with('/') {
+                (text.to_string(), true)
+            } else {
+                return Err(invalid("paths must be absolute or start with ~/"));
+            };
+            if glob.is_empty() {
+                return Err(invalid("empty pattern"));
+            }
+            rules.push(Rule {
+                about: IgnoreRule {
+                    pattern: pattern.clone(),
+                    exclude,
+                    origin: format!("{file}: {key}"),
+                },
+                glob: Pattern::new(&glob).map_err(|e| invalid(&e.to_string()))?,
+                anchored,
+                base: None,
+            });
+        }
+    }
+    Ok(rules)
+}
+
+fn config_file(config: &Config) -> String {
+    config
+        .path
+        .as_ref()
+        .map_or("config".to_string(), |path| path.display().to_string())
+}
+
+/// Reads a `.bdignore`: one pattern per line, `#` comments, `!` to re-include. Patterns
+/// with a `/` are relative to the file's directory.
+fn file_rules(file: &Path) -> Result<Vec<Rule>, Error> {
+    let text = std::fs::read_to_string(file)
+        .map_err(|e| Error::Config(format!("{}: {e}", file.display())))?;
+    let base = file.parent().unwrap_or(Path::new("/")).to_path_buf();
+    let mut rules = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let origin = format!("{}:{}", file.display(), index + 1);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (exclude, text) = match line.strip_prefix('!') {
+            Some(rest) => (false, rest),
+            None => (true, line),
+        };
+        let text = text.trim_end_matches('/');
+        let anchored = text.contains('/');
+        let glob = if anchored {
+            format!(
+                "{}/{}",
+                Pattern::escape(base.to_string_lossy().trim_end_matches('/')),
+                text.trim_start_matches('/')
+            )
+        } else {
+            text.to_string()
+        };
+        if text.is_empty() {
+            return Err(Error::Config(format!("{origin}: empty pattern")));
+        }
+        rules.push(Rule {
+            about: IgnoreRule {
+                pattern: line.to_string(),
+                exclude,
+                origin: origin.clone(),
+            },
+            glob: Pattern::new(&glob).map_err(|e| Error::Config(format!("{origin}: {e}")))?,
+            anchored,
+            base: Some(base.clone()),
+        });
+    }
+    Ok(rules)
+}
+
+/// Returns the last rule matching `path` (or `real_path`), if any.
+pub(crate) fn matching_rule(
+    config: &Config,
+    path: &str,
+    real_path: &str,
+) -> Result<Option<IgnoreRule>, Error> {
+    let mut rules = config_rules(config)
+        .map_err(|msg| Error::Config(format!("{}: {msg}", config_file(config))))?;
+    let dirs: Vec<&Path> = Path::new(path).ancestors().collect();
+    for dir in dirs.into_iter().rev() {
+        let file = dir.join(IGNORE_FILE);
+        if file.is_file() {
+            rules.extend(file_rules(&file)?);
+        }
+    }
+
+    let (path, real_path) = (Path::new(path), Path::new(real_path));
+    Ok(rules
+        .into_iter()
+        .rev()
+        .find(|rule| rule.matches(path) || rule.matches(real_path))
+        .map(|rule| rule.about))
+}
+
+impl Config {
+    /// Explains whether `record` would keep `path` out of history: the deciding rule, or
+    /// `None` when no rule matches and the directory is recorded. `path` must be absolute.
+    pub fn check_ignore(&self, path: &str) -> Result<Option<IgnoreRule>, Error> {
+        let path = normalize_logical(path)?;
+        let real_path = std::fs::canonicalize(&path)
+            .map(|real| real.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| path.clone());
+        matching_rule(self, &path, &real_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn config(text: &str) -> Config {
+        Config::parse(text, Path::new("config.toml")).unwrap()
+    }
+
+    /// Whether `path` is excluded, and by which pattern.
+    fn decision(config: &Config, path: &str) -> Option<(bool, String)> {
+        matching_rule(config, path, path)
+            .unwrap()
+            .map(|rule| (rule.exclude, rule.pattern))
+    }
+
+    #[test]
+    fn unmatched_directories_are_recorded() {
+        let config = config("exclude = [\"node_modules\"]\n");
+        assert!(decision(&config, "/work/app/src").is_none());
+    }
+
+    #[test]
+    fn a_name_pattern_covers_everything_below_it() {
+        let config = config("exclude = [\"node_modules\"]\n");
+        assert_eq!(
+            decision(&config, "/work/app/node_modules/pkg/lib"),
+            Some((true, "node_modules".to_string()))
+        );
+    }
+
+    #[test]
+    fn path_patterns_match_whole_paths() {
+        let config = config("exclude = [\"/tmp/tmp.*\"]\n");
+        assert!(decision(&config, "/tmp/tmp.abc/x").is_some());
+        assert!(decision(&config, "/work/tmp/tmp.abc").is_none());
+    }
+
+    #[test]
+    fn include_overrides_exclude() {
+        let config =
+            config("exclude = [\"node_modules\"]\ninclude = [\"/work/app/node_modules/keep\"]\n");
+        assert_eq!(
+            decision(&config, "/work/app/node_modules/keep/lib"),
+            Some((false, "/work/app/node_modules/keep".to_string()))
+        );
+        assert_eq!(
+            decision(&config, "/work/app/node_modules/other"),
+            Some((true, "node_modules".to_string()))
+        );
+    }
+
+    #[test]
+    fn a_nearer_bdignore_overrides_outer_rules() {
+        let root = std::env::temp_dir().join(format!("bd-test-{}-bdignore", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("app/vendor/node_modules/x")).unwrap();
+        fs::write(root.join(IGNORE_FILE), "# outer\n!node_modules\nbuild/\n").unwrap();
+        fs::write(root.join("app/vendor").join(IGNORE_FILE), "node_modules\n").unwrap();
+        let config = config("exclude = [\"node_modules\"]\n");
+        let path = |rel: &str| root.join(rel).to_string_lossy().into_owned();
+
+        // The outer `.bdignore` re-includes what the config excludes...
+        assert_eq!(
+            decision(&config, &path("app/node_modules")),
+            Some((false, "!node_modules".to_string()))
+        );
+        // ...and the nearer one excludes it again below its own directory.
+        assert_eq!(
+            decision(&config, &path("app/vendor/node_modules/x")),
+            Some((true, "node_modules".to_string()))
+        );
+        assert_eq!(
+            decision(&config, &path("build")),
+            Some((true, "build/".to_string()))
+        );
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn relative_path_patterns_are_rejected() {
+        let invalid = Config::parse("exclude = [\"a/b\"]\n", Path::new("config.toml"));
+        assert!(matches!(invalid, Err(Error::Config(_))));
+    }
+}
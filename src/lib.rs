@@ -0,0 +1,45 @@
+//! Core of `bd`: per-session directory history stored in SQLite.
+//!
+//! [`History`] is the entry point. The `bd-core` binary and the shell wrappers are thin
+//! layers over it; other tools can link this crate to read or drive the same history.
+
+mod config;
+pub mod daemon;
+mod db;
+mod doctor;
+mod error;
+mod forget;
+mod history;
+mod ignore;
+mod marks;
+mod migrate;
+mod paths;
+mod query;
+mod spool;
+mod store;
+
+pub use config::{config_path, Config, Setting, SettingValue, Source};
+pub use db::{db_path, xdg_state_dir};
+pub use doctor::{reset, DoctorReport};
+pub use error::Error;
+pub use forget::{ForgetReport, PruneReport};
+pub use history::{
+    parse_duration, runtime_dir, Direction, History, HistoryEntry, HistoryScope, HistoryView, Move,
+    SessionSummary,
+};
+pub use ignore::IgnoreRule;
+pub use marks::Mark;
+pub use migrate::SCHEMA_VERSION;
+pub use paths::{MatchMode, PathPolicy};
+pub use query::RankedPath;
+pub use spool::{spool_path, spool_record};
+pub use store::{
+    EventFilter, EventRow, EventScan, LastMove, MemoryStore, NewEvent, ParentLink, PathStats,
+    SessionRow, SqliteStore, Store, UndoMove,
+};
+
+/// Default for the largest step count accepted by `back`, `forward` and `list`; see
+/// [`Config::max_back`].
+pub const BD_MAX_BACK: u32 = 999;
+/// Default number of entries shown by `bd ls`; see [`Config::default_list`].
+pub const BD_DEFAULT_LIST: u32 = 10;
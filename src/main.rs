@@ -1,20 +1,30 @@
+use bd_core::{
+    config_path, daemon, db_path, parse_duration, spool_record, Config, Direction, DoctorReport,
+    Error, History, HistoryEntry, HistoryScope, HistoryView, MatchMode, Move, PathPolicy,
+    SessionSummary,
+};
+use clap::builder::FalseyValueParser;
 use clap::{Parser, Subcommand};
-use rusqlite::{params, Connection, OptionalExtension};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, execute, queue, style, terminal};
 use std::env;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
-const BD_MAX_BACK: u32 = 999;
-const BD_DEFAULT_LIST: u32 = 10;
-const CLEANUP_INTERVAL_SECS: i64 = 10 * 24 * 60 * 60; //  10 days
-const SESSION_RETENTION_SECS: i64 = 180 * 24 * 60 * 60; // 180 days
-const UNDO_RETENTION_SECS: i64 = 90 * 24 * 60 * 60; //  90 days
-const META_LAST_CLEANUP_KEY: &str = "last_cleanup_at";
-
 #[derive(Parser)]
 #[command(author, version, about)]
 struct Cli {
+    /// Keep this session's history in memory (snapshotted under $XDG_RUNTIME_DIR, or the
+    /// temp directory without it) instead of the database. Commands that need the
+    /// database refuse it.
+    #[arg(long, global = true, env = "BD_EPHEMERAL", value_parser = FalseyValueParser::new())]
+    ephemeral: bool,
+    /// Use this database instead of $XDG_STATE_HOME/back-directory/bd.sqlite3. Defaults to
+    /// $BD_DB_PATH when that is set and not empty.
+    #[arg(long, global = true, value_name = "PATH")]
+    db: Option<PathBuf>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -26,31 +36,148 @@ enum Commands {
         session: String,
         #[arg(long)]
         pwd: String,
+        #[arg(long, value_enum)]
+        path_policy: Option<PathPolicy>,
+        /// Seed a session without history with the recent history of this one.
+        #[arg(long, value_name = "SESSION")]
+        inherit_from: Option<String>,
     },
     Back {
+        #[arg(long)]
+        session: String,
+        #[arg(long, required_unless_present_any = ["pattern", "ago"])]
+        n: Option<u32>,
+        #[arg(long = "match", value_name = "PATTERN", conflicts_with = "n")]
+        pattern: Option<String>,
+        #[arg(long, value_name = "DURATION", conflicts_with_all = ["n", "pattern"])]
+        ago: Option<String>,
+        #[arg(long, value_enum, default_value_t = MatchMode::Auto, requires = "pattern")]
+        match_mode: MatchMode,
+        #[arg(long, value_enum, default_value_t = HistoryScope::Session)]
+        scope: HistoryScope,
+        #[arg(long)]
+        unique: bool,
+        #[arg(long)]
+        print_path: bool,
+    },
+    Forward {
         #[arg(long)]
         session: String,
         #[arg(long)]
         n: u32,
+        #[arg(long, value_enum, default_value_t = HistoryScope::Session)]
+        scope: HistoryScope,
+        #[arg(long)]
+        unique: bool,
         #[arg(long)]
         print_path: bool,
     },
     List {
         #[arg(long)]
         session: String,
-        #[arg(long, default_value_t = BD_DEFAULT_LIST)]
-        limit: u32,
+        /// Defaults to `default_list` from the config file.
+        #[arg(long)]
+        limit: Option<u32>,
+        #[arg(long)]
+        forward: bool,
+        #[arg(long, value_enum, default_value_t = HistoryScope::Session)]
+        scope: HistoryScope,
+        #[arg(long)]
+        unique: bool,
     },
     Cancel {
         #[arg(long)]
         session: String,
     },
+    Up {
+        #[arg(long)]
+        session: String,
+        #[arg(long)]
+        pwd: String,
+        #[arg(default_value = "1")]
+        target: String,
+        #[arg(long)]
+        print_path: bool,
+        #[arg(long, value_enum)]
+        path_policy: Option<PathPolicy>,
+    },
+    Pick {
+        #[arg(long)]
+        session: String,
+        /// Defaults to `max_back` from the config file.
+        #[arg(long)]
+        limit: Option<u32>,
+        #[arg(long, value_enum, default_value_t = HistoryScope::Session)]
+        scope: HistoryScope,
+        #[arg(long)]
+        unique: bool,
+    },
+    Query {
+        terms: Vec<String>,
+        #[arg(long)]
+        list: bool,
+        /// Defaults to `default_list` from the config file.
+        #[arg(long)]
+        limit: Option<u32>,
+        #[arg(long)]
+        exclude: Option<String>,
+    },
+    Mark {
+        #[command(subcommand)]
+        action: MarkAction,
+    },
     Doctor {
         #[arg(long)]
         integrity: bool,
         #[arg(long)]
         json: bool,
     },
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Pause or resume recording for a session.
+    Session {
+        #[command(subcommand)]
+        action: SessionAction,
+    },
+    /// List the sessions in the database, most recently seen first.
+    Sessions {
+        /// The calling session, marked with `*`.
+        #[arg(long)]
+        session: Option<String>,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete PATH from history (with --recursive, everything below it too).
+    Forget {
+        path: String,
+        #[arg(long, required_unless_present = "all_sessions")]
+        session: Option<String>,
+        #[arg(long)]
+        recursive: bool,
+        /// Forget PATH in every session instead of only --session.
+        #[arg(long)]
+        all_sessions: bool,
+    },
+    /// Delete history entries whose directories no longer exist.
+    Prune {
+        #[arg(long, required_unless_present = "all")]
+        session: Option<String>,
+        /// Prune every session instead of only --session.
+        #[arg(long)]
+        all: bool,
+        /// Report what would be removed without deleting anything.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Explain whether `record` would leave PATH out of history, and which rule decides.
+    CheckIgnore {
+        path: String,
+    },
+    /// Keep the database open and serve record, back, forward, list and cancel over a Unix
+    /// socket next to it. Other invocations use it while it runs.
+    Daemon,
     Optimize,
     Vacuum {
         #[arg(long)]
@@ -60,630 +187,763 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum MarkAction {
+    /// Bookmark a directory (defaults to the current directory).
+    Set {
+        name: String,
+        #[arg(long)]
+        pwd: Option<String>,
+    },
+    /// Print the directory a bookmark points to.
+    Get { name: String },
+    /// List all bookmarks.
+    List,
+    /// Remove a bookmark.
+    Rm { name: String },
+}
+
+#[derive(Subcommand)]
+enum SessionAction {
+    /// Stop recording directories for this session; existing history stays usable.
+    Pause {
+        #[arg(long)]
+        session: String,
+    },
+    /// Record directories again.
+    Resume {
+        #[arg(long)]
+        session: String,
+    },
+    /// Print `paused` or `recording`.
+    Status {
+        #[arg(long)]
+        session: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the effective settings and where each one comes from.
+    Show,
+    /// Check the config file and report the first problem found.
+    Validate,
+}
+
 fn main() {
     let cli = Cli::parse();
+    let backend = Backend {
+        db: cli.db.or_else(|| {
+            env::var_os("BD_DB_PATH")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+        }),
+        ephemeral: cli.ephemeral,
+    };
+    let backend = &backend;
     let result = match cli.command {
-        Commands::Record { session, pwd } => cmd_record(&session, &pwd),
-        Commands::Back {
+        command @ (Commands::Record { .. }
+        | Commands::Back { .. }
+        | Commands::Forward { .. }
+        | Commands::List { .. }
+        | Commands::Cancel { .. }) => cmd_served(command, backend),
+        Commands::Up {
             session,
-            n,
-            print_path,
-        } => cmd_back(&session, n, print_path),
-        Commands::List { session, limit } => cmd_list(&session, limit),
-        Commands::Cancel { session } => cmd_cancel(&session),
-        Commands::Doctor { integrity, json } => cmd_doctor(integrity, json),
-        Commands::Optimize => cmd_optimize(),
-        Commands::Vacuum { yes, y } => cmd_vacuum(yes || y),
+            pwd,
+            target,
+            print_path: _,
+            path_policy,
+        } => open_history(&session, backend)
+            .and_then(|mut history| {
+                let policy = path_policy.unwrap_or(PathPolicy::Logical);
+                history.up(&session, &pwd, &target, policy)
+            })
+            .and_then(|step| write_move(&mut std::io::stdout(), &step)),
+        Commands::Pick {
+            session,
+            limit,
+            scope,
+            unique,
+        } => {
+            let view = HistoryView {
+                session: &session,
+                scope,
+                unique,
+            };
+            cmd_pick(&view, limit, backend)
+        }
+        Commands::Query {
+            terms,
+            list,
+            limit,
+            exclude,
+        } => cmd_query(&terms, list, limit, exclude.as_deref(), backend),
+        Commands::Mark { action } => cmd_mark(action, backend),
+        Commands::Doctor { integrity, json } => cmd_doctor(integrity, json, backend),
+        Commands::Session { action } => cmd_session(action, backend),
+        Commands::Sessions { session, json } => cmd_sessions(session.as_deref(), json, backend),
+        Commands::Config { action } => cmd_config(action),
+        Commands::Forget {
+            path,
+            session,
+            recursive,
+            all_sessions,
+        } => cmd_forget(&path, session.as_deref(), recursive, all_sessions, backend),
+        Commands::Prune {
+            session,
+            all,
+            dry_run,
+        } => cmd_prune(session.as_deref(), all, dry_run, backend),
+        Commands::CheckIgnore { path } => cmd_check_ignore(&path),
+        Commands::Daemon => cmd_daemon(backend),
+        Commands::Optimize => backend.open().and_then(|history| history.optimize()),
+        Commands::Vacuum { yes, y } => cmd_vacuum(yes || y, backend),
     };
 
-    if let Err(msg) = result {
-        eprintln!("{msg}");
-        std::process::exit(1);
+    if let Err(err) = result {
+        eprintln!("{err}");
+        std::process::exit(err.exit_code());
     }
 }
 
-fn cmd_record(session: &str, pwd: &str) -> Result<(), String> {
-    let path = Path::new(pwd);
-    if !path.is_dir() {
-        return Err("bd: pwd is not a directory".to_string());
+/// Where history lives, from the global `--db` and `--ephemeral` options.
+struct Backend {
+    db: Option<PathBuf>,
+    ephemeral: bool,
+}
+
+impl Backend {
+    /// `--db` (or `BD_DB_PATH`), or the default location. A relative path is made
+    /// absolute, so a daemon and its clients started from other directories name the same
+    /// database and socket.
+    fn db_path(&self) -> Result<PathBuf, Error> {
+        match &self.db {
+            Some(path) if path.is_relative() => Ok(env::current_dir()
+                .map_err(|e| Error::Other(format!("cannot read current directory: {e}")))?
+                .join(path)),
+            Some(path) => Ok(path.clone()),
+            None => db_path(),
+        }
     }
 
-    let mut conn = open_db()?;
-    maybe_run_cleanup(&mut conn, session)?;
-    let tx = conn
-        .transaction()
-        .map_err(|e| format!("bd: db error: {e}"))?;
+    fn require_database(&self) -> Result<(), Error> {
+        if self.ephemeral {
+            return Err(Error::Usage(
+                "--ephemeral keeps session history out of the database, which this command needs"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
 
-    let last_path: Option<String> = tx
-        .query_row(
-            "SELECT path FROM events WHERE session_key = ?1 ORDER BY id DESC LIMIT 1",
-            params![session],
-            |row| row.get(0),
-        )
-        .optional()
-        .map_err(|e| format!("bd: db error: {e}"))?;
+    /// Opens the database with the user's config. Commands that get here need the database,
+    /// so `--ephemeral` is refused rather than creating one.
+    fn open(&self) -> Result<History, Error> {
+        self.require_database()?;
+        let config = Config::load()?;
+        Ok(History::open_at(&self.db_path()?)?.with_config(config))
+    }
+}
 
-    let now = current_ts();
-    let mut latest_id = None;
-    if last_path.as_deref() != Some(pwd) {
-        tx.execute(
-            "INSERT INTO events (session_key, path, ts) VALUES (?1, ?2, ?3)",
-            params![session, pwd, now],
-        )
-        .map_err(|e| format!("bd: db error: {e}"))?;
-        latest_id = Some(tx.last_insert_rowid());
-    }
-
-    if latest_id.is_none() {
-        latest_id = tx
-            .query_row(
-                "SELECT id FROM events WHERE session_key = ?1 ORDER BY id DESC LIMIT 1",
-                params![session],
-                |row| row.get(0),
-            )
-            .optional()
-            .map_err(|e| format!("bd: db error: {e}"))?;
+/// Opens the history that session-scoped commands record into and move through.
+fn open_history(session: &str, backend: &Backend) -> Result<History, Error> {
+    if backend.ephemeral {
+        History::ephemeral(session)
+    } else {
+        backend.open()
     }
+}
 
-    let latest_id = match latest_id {
-        Some(id) => id,
-        None => {
-            tx.execute(
-                "INSERT INTO events (session_key, path, ts) VALUES (?1, ?2, ?3)",
-                params![session, pwd, now],
-            )
-            .map_err(|e| format!("bd: db error: {e}"))?;
-            tx.last_insert_rowid()
-        }
+/// The session of a command that `bd-core daemon` can run, or `None` for the others.
+fn served_session(command: &Commands) -> Option<&str> {
+    match command {
+        Commands::Record { session, .. }
+        | Commands::Back { session, .. }
+        | Commands::Forward { session, .. }
+        | Commands::List { session, .. }
+        | Commands::Cancel { session } => Some(session),
+        _ => None,
+    }
+}
+
+/// Runs one of the commands on the prompt hot path, through `bd-core daemon` when one is
+/// serving the database and directly otherwise.
+fn cmd_served(command: Commands, backend: &Backend) -> Result<(), Error> {
+    let db = if backend.ephemeral {
+        None
+    } else {
+        Some(backend.db_path()?)
     };
+    if let Some(db) = &db {
+        // Arguments that are not UTF-8 cannot be forwarded; run those directly.
+        let args: Option<Vec<String>> = env::args_os()
+            .skip(1)
+            .map(|arg| arg.into_string().ok())
+            .collect();
+        if let Some(args) = args {
+            if let Some(response) = daemon::forward(&daemon::socket_path(db), &args)? {
+                print!("{}", response.stdout);
+                eprint!("{}", response.stderr);
+                if response.code != 0 {
+                    let _ = std::io::stdout().flush();
+                    std::process::exit(response.code);
+                }
+                return Ok(());
+            }
+        }
+    }
 
-    tx.execute(
-        "INSERT INTO sessions (session_key, cursor_id, last_bd_delta, last_bd_from_id, last_bd_to_id, last_bd_armed, last_seen_at)
-         VALUES (?1, ?2, 0, 0, 0, 0, ?3)
-         ON CONFLICT(session_key) DO UPDATE SET
-           cursor_id = excluded.cursor_id,
-           last_bd_delta = 0,
-           last_bd_from_id = 0,
-           last_bd_to_id = 0,
-           last_bd_armed = 0,
-           last_seen_at = excluded.last_seen_at",
-        params![session, latest_id, now],
-    )
-    .map_err(|e| format!("bd: db error: {e}"))?;
-
-    tx.execute(
-        "DELETE FROM undo_moves WHERE session_key = ?1",
-        params![session],
-    )
-    .map_err(|e| format!("bd: db error: {e}"))?;
-
-    rotate_events(&tx, session)?;
-    tx.commit().map_err(|e| format!("bd: db error: {e}"))?;
-    Ok(())
+    let session = served_session(&command).unwrap_or_default().to_string();
+    let record = record_args(&command);
+    let result = open_history(&session, backend)
+        .and_then(|mut history| run_served(command, &mut history, &mut std::io::stdout()));
+    spool_if_locked(result, record, db.as_deref())
 }
 
-fn cmd_back(session: &str, n: u32, _print_path: bool) -> Result<(), String> {
-    if n == 0 {
-        return Err("bd: usage: bd [N|c|ls]".to_string());
-    }
-    if n > BD_MAX_BACK {
-        return Err(format!("bd: max is {BD_MAX_BACK}"));
-    }
-
-    let mut conn = open_db()?;
-    maybe_run_cleanup(&mut conn, session)?;
-    let tx = conn
-        .transaction()
-        .map_err(|e| format!("bd: db error: {e}"))?;
-
-    let latest_id: Option<i64> = tx
-        .query_row(
-            "SELECT id FROM events WHERE session_key = ?1 ORDER BY id DESC LIMIT 1",
-            params![session],
-            |row| row.get(0),
-        )
-        .optional()
-        .map_err(|e| format!("bd: db error: {e}"))?;
-
-    let mut cursor_id: i64 = match tx
-        .query_row(
-            "SELECT cursor_id FROM sessions WHERE session_key = ?1",
-            params![session],
-            |row| row.get(0),
-        )
-        .optional()
-        .map_err(|e| format!("bd: db error: {e}"))?
-    {
-        Some(id) => id,
-        None => latest_id.ok_or_else(|| "bd: no earlier directory".to_string())?,
+/// Runs a command that `bd-core daemon` can serve against `history`, writing its output
+/// to `out`. Other commands are refused.
+fn run_served(command: Commands, history: &mut History, out: &mut dyn Write) -> Result<(), Error> {
+    let step = match command {
+        Commands::Record {
+            session,
+            pwd,
+            path_policy,
+            inherit_from,
+        } => {
+            if let Some(parent) = inherit_from {
+                history.inherit(&session, &parent)?;
+            }
+            return history.record(&session, &pwd, path_policy.unwrap_or(PathPolicy::Logical));
+        }
+        Commands::Back {
+            session,
+            n,
+            pattern,
+            ago,
+            match_mode,
+            scope,
+            unique,
+            print_path: _,
+        } => {
+            let view = HistoryView {
+                session: &session,
+                scope,
+                unique,
+            };
+            match (pattern, ago) {
+                (Some(pattern), _) => history.back_match(&view, &pattern, match_mode)?,
+                (None, Some(ago)) => history.back_ago(&session, parse_duration(&ago)?)?,
+                (None, None) => history.back(&view, n.unwrap_or(1))?,
+            }
+        }
+        Commands::Forward {
+            session,
+            n,
+            scope,
+            unique,
+            print_path: _,
+        } => {
+            let view = HistoryView {
+                session: &session,
+                scope,
+                unique,
+            };
+            history.forward(&view, n)?
+        }
+        Commands::List {
+            session,
+            limit,
+            forward,
+            scope,
+            unique,
+        } => {
+            let view = HistoryView {
+                session: &session,
+                scope,
+                unique,
+            };
+            return cmd_list(history, &view, limit, forward, out);
+        }
+        Commands::Cancel { session } => history.cancel(&session)?,
+        _ => {
+            return Err(Error::Usage(
+                "bd-core daemon only serves record, back, forward, list and cancel".to_string(),
+            ))
+        }
     };
+    write_move(out, &step)
+}
 
-    let cursor_exists: Option<i64> = tx
-        .query_row(
-            "SELECT id FROM events WHERE id = ?1 AND session_key = ?2",
-            params![cursor_id, session],
-            |row| row.get(0),
-        )
-        .optional()
-        .map_err(|e| format!("bd: db error: {e}"))?;
-
-    if cursor_exists.is_none() {
-        cursor_id = latest_id.ok_or_else(|| "bd: no earlier directory".to_string())?;
+fn cmd_daemon(backend: &Backend) -> Result<(), Error> {
+    if backend.ephemeral {
+        return Err(Error::Usage(
+            "bd-core daemon serves the database, not an ephemeral store".to_string(),
+        ));
     }
-
-    let (target_id, target_path, actual_steps) = {
-        let mut stmt = tx
-            .prepare(
-                "SELECT id, path FROM events WHERE session_key = ?1 AND id < ?2 ORDER BY id DESC",
-            )
-            .map_err(|e| format!("bd: db error: {e}"))?;
-
-        let mut rows = stmt
-            .query(params![session, cursor_id])
-            .map_err(|e| format!("bd: db error: {e}"))?;
-
-        let mut steps: u32 = 0;
-        let mut target: Option<(i64, String, u32)> = None;
-        let mut oldest_existing: Option<(i64, String, u32)> = None;
-
-        while let Some(row) = rows.next().map_err(|e| format!("bd: db error: {e}"))? {
-            let id: i64 = row.get(0).map_err(|e| format!("bd: db error: {e}"))?;
-            let path: String = row.get(1).map_err(|e| format!("bd: db error: {e}"))?;
-            steps += 1;
-
-            if Path::new(&path).is_dir() {
-                oldest_existing = Some((id, path.clone(), steps));
-                if steps >= n {
-                    target = Some((id, path, steps));
-                    break;
+    let db = backend.db_path()?;
+    let config = config_path()?;
+    let mut history = backend.open()?;
+    let mut file_id = db_file_id(&db);
+    let mut config_mtime = file_mtime(&config);
+    let socket = daemon::socket_path(&db);
+    daemon::serve(&socket, |args| {
+        // `bd vacuum` replaces the database file; follow it rather than keep writing to
+        // the deleted one. An edited config file is picked up the same way.
+        if db_file_id(&db) != file_id || file_mtime(&config) != config_mtime {
+            match backend.open() {
+                Ok(reopened) => {
+                    history = reopened;
+                    file_id = db_file_id(&db);
+                    config_mtime = file_mtime(&config);
                 }
+                Err(err) => return error_response(&err),
             }
         }
-
-        match target.or(oldest_existing) {
-            Some(value) => value,
-            None => return Err("bd: no earlier directory".to_string()),
+        let command = match Cli::try_parse_from(std::iter::once("bd-core".to_string()).chain(args))
+        {
+            Ok(cli) => cli.command,
+            Err(err) => {
+                return daemon::Response {
+                    code: err.exit_code(),
+                    stdout: String::new(),
+                    stderr: err.render().to_string(),
+                }
+            }
+        };
+        let record = record_args(&command);
+        let mut out = Vec::new();
+        let result = run_served(command, &mut history, &mut out);
+        match spool_if_locked(result, record, Some(&db)) {
+            Ok(()) => daemon::Response {
+                code: 0,
+                stdout: String::from_utf8_lossy(&out).into_owned(),
+                stderr: String::new(),
+            },
+            Err(err) => error_response(&err),
         }
-    };
+    })
+}
 
-    let now = current_ts();
-    tx.execute(
-        "INSERT INTO sessions (session_key, cursor_id, last_bd_delta, last_bd_from_id, last_bd_to_id, last_bd_armed, last_seen_at)
-         VALUES (?1, ?2, ?3, ?4, ?5, 1, ?6)
-         ON CONFLICT(session_key) DO UPDATE SET
-           cursor_id = excluded.cursor_id,
-           last_bd_delta = excluded.last_bd_delta,
-           last_bd_from_id = excluded.last_bd_from_id,
-           last_bd_to_id = excluded.last_bd_to_id,
-           last_bd_armed = 1,
-           last_seen_at = excluded.last_seen_at",
-        params![session, target_id, actual_steps, cursor_id, target_id, now],
-    )
-    .map_err(|e| format!("bd: db error: {e}"))?;
-
-    tx.execute(
-        "INSERT INTO undo_moves (session_key, from_id, to_id, created_at) VALUES (?1, ?2, ?3, ?4)",
-        params![session, cursor_id, target_id, now],
-    )
-    .map_err(|e| format!("bd: db error: {e}"))?;
-
-    tx.commit().map_err(|e| format!("bd: db error: {e}"))?;
-
-    println!("{target_path}");
-    Ok(())
+fn error_response(err: &Error) -> daemon::Response {
+    daemon::Response {
+        code: err.exit_code(),
+        stdout: String::new(),
+        stderr: format!("{err}\n"),
+    }
 }
 
-fn cmd_list(session: &str, limit: u32) -> Result<(), String> {
-    if limit == 0 {
-        return Err("bd: usage: bd ls [N]".to_string());
-    }
-    if limit > BD_MAX_BACK {
-        return Err(format!("bd: max is {BD_MAX_BACK}"));
-    }
-
-    let mut conn = open_db()?;
-    maybe_run_cleanup(&mut conn, session)?;
-    let tx = conn
-        .transaction()
-        .map_err(|e| format!("bd: db error: {e}"))?;
-
-    let latest_id: Option<i64> = tx
-        .query_row(
-            "SELECT id FROM events WHERE session_key = ?1 ORDER BY id DESC LIMIT 1",
-            params![session],
-            |row| row.get(0),
-        )
-        .optional()
-        .map_err(|e| format!("bd: db error: {e}"))?;
-
-    let mut cursor_id: i64 = match tx
-        .query_row(
-            "SELECT cursor_id FROM sessions WHERE session_key = ?1",
-            params![session],
-            |row| row.get(0),
-        )
-        .optional()
-        .map_err(|e| format!("bd: db error: {e}"))?
-    {
-        Some(id) => id,
-        None => latest_id.ok_or_else(|| "bd: no history in this session".to_string())?,
-    };
+/// When the file at `path` was last modified, to notice when it is edited.
+fn file_mtime(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
 
-    let cursor_exists: Option<i64> = tx
-        .query_row(
-            "SELECT id FROM events WHERE id = ?1 AND session_key = ?2",
-            params![cursor_id, session],
-            |row| row.get(0),
-        )
-        .optional()
-        .map_err(|e| format!("bd: db error: {e}"))?;
+/// Identifies the file at `path`, to notice when it is deleted and recreated.
+fn db_file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path)
+        .ok()
+        .map(|meta| (meta.dev(), meta.ino()))
+}
 
-    if cursor_exists.is_none() {
-        cursor_id = latest_id.ok_or_else(|| "bd: no history in this session".to_string())?;
+/// The arguments of a `record` command, kept to spool it if the database is locked.
+fn record_args(command: &Commands) -> Option<(String, String, PathPolicy)> {
+    match command {
+        Commands::Record {
+            session,
+            pwd,
+            path_policy,
+            ..
+        } => Some((
+            session.clone(),
+            pwd.clone(),
+            path_policy.unwrap_or(PathPolicy::Logical),
+        )),
+        _ => None,
     }
+}
 
-    let now = current_ts();
-    tx.execute(
-        "UPDATE sessions SET last_seen_at = ?1 WHERE session_key = ?2",
-        params![now, session],
-    )
-    .map_err(|e| format!("bd: db error: {e}"))?;
-
-    let mut stmt = tx
-        .prepare("SELECT id, path FROM events WHERE session_key = ?1 AND id < ?2 ORDER BY id DESC")
-        .map_err(|e| format!("bd: db error: {e}"))?;
-
-    let mut rows = stmt
-        .query(params![session, cursor_id])
-        .map_err(|e| format!("bd: db error: {e}"))?;
-
-    let mut steps: u32 = 0;
-    let mut printed = 0;
-    let mut lines: Vec<(u32, String)> = Vec::new();
-    while let Some(row) = rows.next().map_err(|e| format!("bd: db error: {e}"))? {
-        let _id: i64 = row.get(0).map_err(|e| format!("bd: db error: {e}"))?;
-        let path: String = row.get(1).map_err(|e| format!("bd: db error: {e}"))?;
-        steps += 1;
-        if Path::new(&path).is_dir() {
-            lines.push((steps, path));
-            printed += 1;
-            if printed >= limit {
-                break;
-            }
+/// A `record` that finds the database locked past the busy timeout is spooled for the
+/// next command to replay rather than lost.
+fn spool_if_locked(
+    result: Result<(), Error>,
+    record: Option<(String, String, PathPolicy)>,
+    db: Option<&Path>,
+) -> Result<(), Error> {
+    match (result, record, db) {
+        (Err(Error::Locked(_)), Some((session, pwd, policy)), Some(db)) => {
+            spool_record(db, &session, &pwd, policy)
         }
+        (result, _, _) => result,
     }
+}
 
-    if printed == 0 {
-        return Err("bd: no history in this session".to_string());
-    }
+/// The wrappers `cd` into whatever a navigation command prints on stdout.
+fn write_move(out: &mut dyn Write, step: &Move) -> Result<(), Error> {
+    writeln!(out, "{}", step.path)?;
+    Ok(())
+}
 
-    let max_step = lines.iter().map(|(step, _)| *step).max().unwrap_or(0);
-    let width = max_step.to_string().len();
-    let home_raw = std::env::var("HOME").unwrap_or_default();
-    let home = if home_raw.ends_with('/') && home_raw.len() > 1 {
-        home_raw.trim_end_matches('/').to_string()
+fn cmd_list(
+    history: &mut History,
+    view: &HistoryView<'_>,
+    limit: Option<u32>,
+    forward: bool,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let direction = if forward {
+        Direction::Forward
     } else {
-        home_raw
+        Direction::Back
     };
-    for (step, path) in lines.into_iter().rev() {
-        let display_path = if !home.is_empty() && path == home {
-            "~".to_string()
-        } else if !home.is_empty() && path.starts_with(&(home.clone() + "/")) {
-            format!("~{}", &path[home.len()..])
-        } else {
-            path
-        };
-        println!("[{:>width$}] {}", step, display_path, width = width);
+    let limit = limit.unwrap_or(history.config().default_list.value);
+    let entries = history.list(view, direction, limit)?;
+    let mut lines: Vec<(u32, String)> = entries
+        .into_iter()
+        .map(|entry| (entry.steps, entry.path))
+        .collect();
+
+    // Both directions print oldest first, so the entry nearest the cursor sits next
+    // to the prompt for `bd ls` and at the top for `bd f ls`.
+    if direction == Direction::Back {
+        lines.reverse();
     }
-
+    write_history_lines(out, &lines)?;
     Ok(())
 }
 
-fn cmd_cancel(session: &str) -> Result<(), String> {
-    let mut conn = open_db()?;
-    maybe_run_cleanup(&mut conn, session)?;
-    let tx = conn
-        .transaction()
-        .map_err(|e| format!("bd: db error: {e}"))?;
-
-    let row: Option<(i64, i64)> = tx
-        .query_row(
-            "SELECT id, from_id FROM undo_moves WHERE session_key = ?1 ORDER BY id DESC LIMIT 1",
-            params![session],
-            |row| Ok((row.get(0)?, row.get(1)?)),
-        )
-        .optional()
-        .map_err(|e| format!("bd: db error: {e}"))?;
-
-    let (undo_id, last_bd_from_id) = match row {
-        Some(value) => value,
-        None => return Err("bd: nothing to cancel".to_string()),
-    };
+fn cmd_pick(view: &HistoryView<'_>, limit: Option<u32>, backend: &Backend) -> Result<(), Error> {
+    let mut history = open_history(view.session, backend)?;
+    let max_back = history.config().max_back.value;
+    let limit = limit.unwrap_or(max_back);
+    if limit == 0 {
+        return Err(Error::Usage("usage: bd p".to_string()));
+    }
+    if limit > max_back {
+        return Err(Error::Usage(format!("max is {max_back}")));
+    }
+
+    let entries: Vec<HistoryEntry> = history.entries(view, limit)?;
+    let home = display_home();
+    let picks: Vec<PickEntry> = entries
+        .iter()
+        .map(|entry| PickEntry {
+            steps: entry.steps,
+            label: display_path(&entry.path, &home),
+            exists: Path::new(&entry.path).is_dir(),
+        })
+        .collect();
+
+    if !picks.iter().any(|pick| pick.exists) {
+        return Err(Error::EmptyHistory(
+            "no history in this session".to_string(),
+        ));
+    }
 
-    let target_path: Option<String> = tx
-        .query_row(
-            "SELECT path FROM events WHERE id = ?1 AND session_key = ?2",
-            params![last_bd_from_id, session],
-            |row| row.get(0),
-        )
-        .optional()
-        .map_err(|e| format!("bd: db error: {e}"))?;
-
-    let target_path = match target_path {
-        Some(path) if Path::new(&path).is_dir() => path,
-        _ => return Err("bd: nothing to cancel".to_string()),
+    // The database is not held open while the picker waits for input.
+    drop(history);
+    let chosen = run_picker(&picks).map_err(|e| Error::Other(format!("terminal error: {e}")))?;
+    let entry = match chosen {
+        Some(index) => &entries[index],
+        None => return Err(Error::Other("pick cancelled".to_string())),
     };
 
-    let now = current_ts();
-    tx.execute(
-        "UPDATE sessions SET cursor_id = ?1, last_bd_delta = 0, last_bd_from_id = 0, last_bd_to_id = 0, last_bd_armed = 0,
-         last_seen_at = ?2 WHERE session_key = ?3",
-        params![last_bd_from_id, now, session],
-    )
-    .map_err(|e| format!("bd: db error: {e}"))?;
+    let step = open_history(view.session, backend)?.jump_to(view, entry)?;
+    write_move(&mut std::io::stdout(), &step)
+}
+
+struct PickEntry {
+    steps: u32,
+    label: String,
+    exists: bool,
+}
 
-    tx.execute("DELETE FROM undo_moves WHERE id = ?1", params![undo_id])
-        .map_err(|e| format!("bd: db error: {e}"))?;
+/// Restores the terminal even when the picker returns early or panics.
+struct TerminalGuard;
 
-    tx.commit().map_err(|e| format!("bd: db error: {e}"))?;
+impl TerminalGuard {
+    fn enter(out: &mut impl Write) -> std::io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let guard = TerminalGuard;
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(guard)
+    }
+}
 
-    println!("{target_path}");
-    Ok(())
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(
+            std::io::stderr(),
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
 }
 
-fn open_db() -> Result<Connection, String> {
-    let path = db_path()?;
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent).map_err(|e| format!("bd: db error: {e}"))?;
-    }
-
-    let conn = Connection::open(path).map_err(|e| format!("bd: db error: {e}"))?;
-    conn.execute_batch(
-        "PRAGMA journal_mode = WAL;
-         PRAGMA synchronous = NORMAL;
-         PRAGMA temp_store = MEMORY;
-         CREATE TABLE IF NOT EXISTS events (
-           id INTEGER PRIMARY KEY AUTOINCREMENT,
-           session_key TEXT NOT NULL DEFAULT '',
-           path TEXT NOT NULL,
-           ts INTEGER NOT NULL
-         );
-         CREATE INDEX IF NOT EXISTS idx_events_session_id ON events(session_key, id);
-         CREATE INDEX IF NOT EXISTS idx_events_ts ON events(ts);
-         CREATE TABLE IF NOT EXISTS sessions (
-           session_key TEXT PRIMARY KEY,
-           cursor_id INTEGER NOT NULL,
-           last_bd_delta INTEGER NOT NULL DEFAULT 0,
-           last_bd_from_id INTEGER NOT NULL DEFAULT 0,
-           last_bd_to_id INTEGER NOT NULL DEFAULT 0,
-           last_bd_armed INTEGER NOT NULL DEFAULT 0,
-           last_seen_at INTEGER NOT NULL DEFAULT 0
-         );
-         CREATE TABLE IF NOT EXISTS undo_moves (
-           id INTEGER PRIMARY KEY AUTOINCREMENT,
-           session_key TEXT NOT NULL,
-           from_id INTEGER NOT NULL,
-           to_id INTEGER NOT NULL,
-           created_at INTEGER NOT NULL DEFAULT 0
-         );
-         CREATE INDEX IF NOT EXISTS idx_undo_moves_session_id ON undo_moves(session_key, id);
-         CREATE TABLE IF NOT EXISTS meta (
-           key TEXT PRIMARY KEY,
-           value INTEGER NOT NULL
-         );
-         ",
-    )
-    .map_err(|e| format!("bd: db error: {e}"))?;
-
-    ensure_schema(&conn)?;
-    Ok(conn)
-}
-
-fn xdg_state_dir() -> Result<PathBuf, String> {
-    if let Ok(state_home) = env::var("XDG_STATE_HOME") {
-        return Ok(PathBuf::from(state_home).join("back-directory"));
-    }
-
-    let home = env::var("HOME").map_err(|_| "bd: HOME not set".to_string())?;
-    Ok(PathBuf::from(home)
-        .join(".local")
-        .join("state")
-        .join("back-directory"))
-}
-
-fn db_path() -> Result<PathBuf, String> {
-    Ok(xdg_state_dir()?.join("bd.sqlite3"))
-}
-
-fn rotate_events(tx: &rusqlite::Transaction<'_>, session: &str) -> Result<(), String> {
-    let min_session_cursor_id: Option<i64> = tx
-        .query_row(
-            "SELECT MIN(val) FROM (
-               SELECT cursor_id AS val FROM sessions WHERE session_key = ?1 AND cursor_id != 0
-               UNION ALL
-               SELECT last_bd_from_id FROM sessions WHERE session_key = ?1 AND last_bd_from_id != 0
-               UNION ALL
-               SELECT last_bd_to_id FROM sessions WHERE session_key = ?1 AND last_bd_to_id != 0
-               UNION ALL
-               SELECT from_id FROM undo_moves WHERE session_key = ?1
-               UNION ALL
-               SELECT to_id FROM undo_moves WHERE session_key = ?1
-             )",
-            params![session],
-            |row| row.get(0),
-        )
-        .map_err(|e| format!("bd: db error: {e}"))?;
-
-    let rotation_cutoff_id: Option<i64> = tx
-        .query_row(
-            "SELECT id FROM events WHERE session_key = ?1 ORDER BY id DESC LIMIT 1 OFFSET 9999",
-            params![session],
-            |row| row.get(0),
-        )
-        .optional()
-        .map_err(|e| format!("bd: db error: {e}"))?;
-
-    let (rotation_cutoff_id, min_session_cursor_id) =
-        match (rotation_cutoff_id, min_session_cursor_id) {
-            (Some(rotation_cutoff_id), Some(min_session_cursor_id)) => {
-                (rotation_cutoff_id, min_session_cursor_id)
+/// Full-screen picker drawn on stderr, since stdout carries the chosen path back to the
+/// wrapper. Returns the index of the selected entry, or `None` when cancelled.
+fn run_picker(entries: &[PickEntry]) -> std::io::Result<Option<usize>> {
+    let mut out = std::io::stderr();
+    let _guard = TerminalGuard::enter(&mut out)?;
+
+    let width = entries
+        .iter()
+        .map(|entry| entry.steps)
+        .max()
+        .unwrap_or(0)
+        .to_string()
+        .len();
+    let mut filter = String::new();
+    let mut selected: usize = 0;
+    let mut offset: usize = 0;
+
+    loop {
+        let needles: Vec<String> = filter.split_whitespace().map(str::to_lowercase).collect();
+        let visible: Vec<usize> = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                let label = entry.label.to_lowercase();
+                needles.iter().all(|needle| label.contains(needle.as_str()))
+            })
+            .map(|(index, _)| index)
+            .collect();
+        selected = selected.min(visible.len().saturating_sub(1));
+
+        let (_, rows) = terminal::size()?;
+        let page = usize::from(rows).saturating_sub(2).max(1);
+        if selected < offset {
+            offset = selected;
+        } else if selected >= offset + page {
+            offset = selected + 1 - page;
+        }
+
+        queue!(
+            out,
+            cursor::MoveTo(0, 0),
+            terminal::Clear(terminal::ClearType::All),
+            style::Print(format!("> {filter}")),
+        )?;
+        for (row, &index) in visible.iter().skip(offset).take(page).enumerate() {
+            let entry = &entries[index];
+            let marker = if entry.exists { ' ' } else { '!' };
+            let line = format!(
+                "{marker} [{:>width$}] {}",
+                entry.steps,
+                entry.label,
+                width = width
+            );
+            queue!(out, cursor::MoveTo(0, row as u16 + 1))?;
+            if offset + row == selected {
+                queue!(
+                    out,
+                    style::SetAttribute(style::Attribute::Reverse),
+                    style::Print(line),
+                    style::SetAttribute(style::Attribute::Reset),
+                )?;
+            } else if !entry.exists {
+                queue!(
+                    out,
+                    style::SetAttribute(style::Attribute::Dim),
+                    style::Print(line),
+                    style::SetAttribute(style::Attribute::Reset),
+                )?;
+            } else {
+                queue!(out, style::Print(line))?;
             }
-            _ => return Ok(()),
+        }
+        queue!(
+            out,
+            cursor::MoveTo(0, rows.saturating_sub(1)),
+            style::Print(format!(
+                "{}/{}  enter: go  esc: cancel  !: missing",
+                visible.len(),
+                entries.len()
+            )),
+        )?;
+        out.flush()?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
         };
-
-    let delete_before = rotation_cutoff_id.min(min_session_cursor_id);
-    if delete_before <= 0 {
-        return Ok(());
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') | KeyCode::Char('g') if ctrl => return Ok(None),
+            KeyCode::Enter => {
+                if let Some(&index) = visible.get(selected) {
+                    if entries[index].exists {
+                        return Ok(Some(index));
+                    }
+                }
+            }
+            KeyCode::Up => selected = selected.saturating_sub(1),
+            KeyCode::Char('p') if ctrl => selected = selected.saturating_sub(1),
+            KeyCode::Down => selected += 1,
+            KeyCode::Char('n') if ctrl => selected += 1,
+            KeyCode::PageUp => selected = selected.saturating_sub(page),
+            KeyCode::PageDown => selected += page,
+            KeyCode::Home => selected = 0,
+            KeyCode::End => selected = visible.len().saturating_sub(1),
+            KeyCode::Backspace => {
+                filter.pop();
+                selected = 0;
+            }
+            KeyCode::Char('u') if ctrl => {
+                filter.clear();
+                selected = 0;
+            }
+            KeyCode::Char(c) if !ctrl => {
+                filter.push(c);
+                selected = 0;
+            }
+            _ => {}
+        }
     }
+}
 
-    tx.execute(
-        "DELETE FROM events WHERE session_key = ?1 AND id < ?2",
-        params![session, delete_before],
-    )
-    .map_err(|e| format!("bd: db error: {e}"))?;
-
+fn write_history_lines(out: &mut dyn Write, lines: &[(u32, String)]) -> std::io::Result<()> {
+    let max_step = lines.iter().map(|(step, _)| *step).max().unwrap_or(0);
+    let width = max_step.to_string().len();
+    let home = display_home();
+    for (step, path) in lines {
+        writeln!(
+            out,
+            "[{:>width$}] {}",
+            step,
+            display_path(path, &home),
+            width = width
+        )?;
+    }
     Ok(())
 }
 
-fn ensure_schema(conn: &Connection) -> Result<(), String> {
-    ensure_column(
-        conn,
-        "sessions",
-        "last_seen_at",
-        "INTEGER NOT NULL DEFAULT 0",
-    )?;
-    ensure_column(
-        conn,
-        "undo_moves",
-        "created_at",
-        "INTEGER NOT NULL DEFAULT 0",
-    )?;
-    Ok(())
+fn display_home() -> String {
+    let home_raw = std::env::var("HOME").unwrap_or_default();
+    if home_raw.ends_with('/') && home_raw.len() > 1 {
+        home_raw.trim_end_matches('/').to_string()
+    } else {
+        home_raw
+    }
 }
 
-fn ensure_column(
-    conn: &Connection,
-    table: &str,
-    column: &str,
-    definition: &str,
-) -> Result<(), String> {
-    let mut stmt = conn
-        .prepare(&format!("PRAGMA table_info({table})"))
-        .map_err(|e| format!("bd: db error: {e}"))?;
-    let mut rows = stmt.query([]).map_err(|e| format!("bd: db error: {e}"))?;
-    while let Some(row) = rows.next().map_err(|e| format!("bd: db error: {e}"))? {
-        let name: String = row.get(1).map_err(|e| format!("bd: db error: {e}"))?;
-        if name == column {
-            return Ok(());
-        }
+fn display_path(path: &str, home: &str) -> String {
+    if !home.is_empty() && path == home {
+        "~".to_string()
+    } else if !home.is_empty() && path.starts_with(&format!("{home}/")) {
+        format!("~{}", &path[home.len()..])
+    } else {
+        path.to_string()
     }
-    conn.execute(
-        &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
-        [],
-    )
-    .map_err(|e| format!("bd: db error: {e}"))?;
-    Ok(())
 }
 
-fn maybe_run_cleanup(conn: &mut Connection, session: &str) -> Result<(), String> {
-    let now = current_ts();
-    let last_cleanup_at: i64 = conn
-        .query_row(
-            "SELECT value FROM meta WHERE key = ?1",
-            params![META_LAST_CLEANUP_KEY],
-            |row| row.get(0),
-        )
-        .optional()
-        .map_err(|e| format!("bd: db error: {e}"))?
-        .unwrap_or(0);
+fn cmd_query(
+    terms: &[String],
+    list: bool,
+    limit: Option<u32>,
+    exclude: Option<&str>,
+    backend: &Backend,
+) -> Result<(), Error> {
+    if terms.is_empty() && !list {
+        return Err(Error::Usage("usage: bd q TERMS...".to_string()));
+    }
+    let mut history = backend.open()?;
+    let config = history.config();
+    let limit = limit.unwrap_or(config.default_list.value);
+    if limit == 0 {
+        return Err(Error::Usage("usage: bd q ls [TERMS...]".to_string()));
+    }
+    if limit > config.max_back.value {
+        return Err(Error::Usage(format!("max is {}", config.max_back.value)));
+    }
 
-    if now.saturating_sub(last_cleanup_at) < CLEANUP_INTERVAL_SECS {
+    let ranked = history.query(terms, exclude)?;
+    if ranked.is_empty() {
+        return Err(Error::EmptyHistory("no match found".to_string()));
+    }
+    if !list {
+        println!("{}", ranked[0].path);
         return Ok(());
     }
 
-    let session_cutoff = now - SESSION_RETENTION_SECS;
-    let undo_cutoff = now - UNDO_RETENTION_SECS;
-    let tx = conn
-        .transaction()
-        .map_err(|e| format!("bd: db error: {e}"))?;
-
-    tx.execute(
-        "DELETE FROM sessions WHERE last_seen_at < ?1 AND session_key != ?2",
-        params![session_cutoff, session],
-    )
-    .map_err(|e| format!("bd: db error: {e}"))?;
-
-    tx.execute(
-        "DELETE FROM undo_moves WHERE created_at < ?1",
-        params![undo_cutoff],
-    )
-    .map_err(|e| format!("bd: db error: {e}"))?;
-
-    tx.execute(
-        "INSERT INTO meta (key, value) VALUES (?1, ?2)
-         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
-        params![META_LAST_CLEANUP_KEY, now],
-    )
-    .map_err(|e| format!("bd: db error: {e}"))?;
-
-    tx.commit().map_err(|e| format!("bd: db error: {e}"))?;
+    let home = display_home();
+    for hit in ranked.iter().take(limit as usize) {
+        println!("{:>8.2} {}", hit.score, display_path(&hit.path, &home));
+    }
     Ok(())
 }
 
-fn cmd_doctor(integrity: bool, json: bool) -> Result<(), String> {
-    let conn = open_db()?;
-    let path = db_path()?;
-    let db_size = file_size(&path);
-    let wal_path = PathBuf::from(format!("{}-wal", path.display()));
-    let shm_path = PathBuf::from(format!("{}-shm", path.display()));
-    let wal_size = file_size(&wal_path);
-    let shm_size = file_size(&shm_path);
-
-    let page_count: i64 = conn
-        .query_row("PRAGMA page_count", [], |row| row.get(0))
-        .map_err(|e| format!("bd: db error: {e}"))?;
-    let freelist_count: i64 = conn
-        .query_row("PRAGMA freelist_count", [], |row| row.get(0))
-        .map_err(|e| format!("bd: db error: {e}"))?;
-    let page_size: i64 = conn
-        .query_row("PRAGMA page_size", [], |row| row.get(0))
-        .map_err(|e| format!("bd: db error: {e}"))?;
-
-    let events_count: i64 = conn
-        .query_row("SELECT COUNT(*) FROM events", [], |row| row.get(0))
-        .map_err(|e| format!("bd: db error: {e}"))?;
-    let sessions_count: i64 = conn
-        .query_row("SELECT COUNT(*) FROM sessions", [], |row| row.get(0))
-        .map_err(|e| format!("bd: db error: {e}"))?;
-    let undo_count: i64 = conn
-        .query_row("SELECT COUNT(*) FROM undo_moves", [], |row| row.get(0))
-        .map_err(|e| format!("bd: db error: {e}"))?;
-
-    let last_cleanup_at: i64 = conn
-        .query_row(
-            "SELECT value FROM meta WHERE key = ?1",
-            params![META_LAST_CLEANUP_KEY],
-            |row| row.get(0),
-        )
-        .optional()
-        .map_err(|e| format!("bd: db error: {e}"))?
-        .unwrap_or(0);
-
-    let integrity = if integrity {
-        let mut stmt = conn
-            .prepare("PRAGMA integrity_check")
-            .map_err(|e| format!("bd: db error: {e}"))?;
-        let rows = stmt
-            .query_map([], |row| row.get::<_, String>(0))
-            .map_err(|e| format!("bd: db error: {e}"))?;
-        let mut results = Vec::new();
-        for row in rows {
-            results.push(row.map_err(|e| format!("bd: db error: {e}"))?);
+fn cmd_mark(action: MarkAction, backend: &Backend) -> Result<(), Error> {
+    let mut history = backend.open()?;
+    match action {
+        MarkAction::Set { name, pwd } => {
+            let pwd = match pwd {
+                Some(pwd) => pwd,
+                None => env::current_dir()
+                    .map_err(|e| Error::Other(format!("cannot read current directory: {e}")))?
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            history.set_mark(&name, &pwd)
         }
-        Some(results)
-    } else {
-        None
-    };
+        MarkAction::Get { name } => {
+            println!("{}", history.mark(&name)?);
+            Ok(())
+        }
+        MarkAction::List => {
+            let marks = history.marks()?;
+            if marks.is_empty() {
+                return Err(Error::EmptyHistory("no marks".to_string()));
+            }
+            let width = marks.iter().map(|mark| mark.name.len()).max().unwrap_or(0);
+            let home = display_home();
+            for mark in marks {
+                let marker = if Path::new(&mark.path).is_dir() {
+                    ""
+                } else {
+                    " (missing)"
+                };
+                println!(
+                    "{:<width$}  {}{}",
+                    mark.name,
+                    display_path(&mark.path, &home),
+                    marker,
+                    width = width
+                );
+            }
+            Ok(())
+        }
+        MarkAction::Rm { name } => history.remove_mark(&name),
+    }
+}
+
+fn cmd_doctor(integrity: bool, json: bool, backend: &Backend) -> Result<(), Error> {
+    let report = backend.open()?.doctor(integrity)?;
+    let optimize_recommended = report.optimize_recommended();
+    let DoctorReport {
+        path,
+        db_size,
+        wal_size,
+        shm_size,
+        page_count,
+        freelist_count,
+        page_size,
+        schema_version,
+        events: events_count,
+        dirs: dirs_count,
+        sessions: sessions_count,
+        undo_moves: undo_count,
+        marks: marks_count,
+        last_cleanup_at,
+        spooled,
+        integrity,
+        config,
+    } = report;
 
     let now = current_ts();
     if json {
@@ -704,14 +964,8 @@ fn cmd_doctor(integrity: bool, json: bool) -> Result<(), String> {
         };
         let last_cleanup_rfc3339 = format_ts(last_cleanup_at);
 
-        let optimize_recommended = if page_count > 0 {
-            (freelist_count as f64 / page_count as f64) >= 0.2
-        } else {
-            false
-        };
-
         let mut payload = format!(
-            "\"database\":\"{db_path}\",\"db_size_bytes\":{db_size},\"wal_size_bytes\":{wal_size},\"shm_size_bytes\":{shm_size},\"page_count\":{page_count},\"freelist_count\":{freelist_count},\"page_size\":{page_size},\"events\":{events},\"sessions\":{sessions},\"undo_moves\":{undo},\"last_cleanup_at\":{last_cleanup_at},\"last_cleanup_at_rfc3339\":{last_cleanup_rfc3339},\"last_cleanup_age_days\":{last_cleanup_age},\"optimize_recommended\":{optimize_recommended}",
+            "\"database\":\"{db_path}\",\"db_size_bytes\":{db_size},\"wal_size_bytes\":{wal_size},\"shm_size_bytes\":{shm_size},\"page_count\":{page_count},\"freelist_count\":{freelist_count},\"page_size\":{page_size},\"schema_version\":{schema_version},\"events\":{events},\"dirs\":{dirs},\"sessions\":{sessions},\"undo_moves\":{undo},\"marks\":{marks},\"last_cleanup_at\":{last_cleanup_at},\"last_cleanup_at_rfc3339\":{last_cleanup_rfc3339},\"last_cleanup_age_days\":{last_cleanup_age},\"optimize_recommended\":{optimize_recommended},\"spool_pending\":{spooled}",
             db_path = db_path_json,
             db_size = db_size.map_or("null".to_string(), |v| v.to_string()),
             wal_size = wal_size.map_or("null".to_string(), |v| v.to_string()),
@@ -719,19 +973,24 @@ fn cmd_doctor(integrity: bool, json: bool) -> Result<(), String> {
             page_count = page_count,
             freelist_count = freelist_count,
             page_size = page_size,
+            schema_version = schema_version,
             events = events_count,
+            dirs = dirs_count,
             sessions = sessions_count,
             undo = undo_count,
+            marks = marks_count,
             last_cleanup_at = last_cleanup_at,
             last_cleanup_rfc3339 = last_cleanup_rfc3339
                 .map(|v| format!("\"{}\"", json_escape(&v)))
                 .unwrap_or_else(|| "null".to_string()),
             last_cleanup_age = last_cleanup_age_days.map_or("null".to_string(), |v| v.to_string()),
             optimize_recommended = optimize_recommended,
+            spooled = spooled,
         );
         if let Some(integrity_json) = integrity_json {
             payload.push_str(&format!(",\"integrity_check\":{integrity_json}"));
         }
+        payload.push_str(&format!(",\"config\":{}", config_json(&config)));
         println!("{{{payload}}}");
         return Ok(());
     }
@@ -756,15 +1015,13 @@ fn cmd_doctor(integrity: bool, json: bool) -> Result<(), String> {
     println!("  page_count: {page_count}");
     println!("  freelist_count: {freelist_count}");
     println!("  page_size: {page_size}");
+    println!("  schema_version: {schema_version}");
     println!("app.tables");
     println!("  events: {events_count}");
+    println!("  dirs: {dirs_count}");
     println!("  sessions: {sessions_count}");
     println!("  undo_moves: {undo_count}");
-    let optimize_recommended = if page_count > 0 {
-        (freelist_count as f64 / page_count as f64) >= 0.2
-    } else {
-        false
-    };
+    println!("  marks: {marks_count}");
     if optimize_recommended {
         println!("optimize");
         println!("  recommended: yes (can be slow)");
@@ -781,6 +1038,13 @@ fn cmd_doctor(integrity: bool, json: bool) -> Result<(), String> {
         println!("cleanup");
         println!("  last_cleanup_at: never");
     }
+    println!("spool");
+    println!("  pending: {spooled}");
+    println!("config");
+    println!("  file: {}", config_file_label(&config));
+    for (key, value, source) in config.settings() {
+        println!("  {key}: {value} ({})", source.as_str());
+    }
     if let Some(rows) = integrity {
         if rows.len() == 1 && rows[0] == "ok" {
             println!("integrity_check: ok");
@@ -794,37 +1058,207 @@ fn cmd_doctor(integrity: bool, json: bool) -> Result<(), String> {
     Ok(())
 }
 
-fn cmd_optimize() -> Result<(), String> {
-    let conn = open_db()?;
-    conn.execute_batch("VACUUM;")
-        .map_err(|e| format!("bd: db error: {e}"))?;
+fn cmd_session(action: SessionAction, backend: &Backend) -> Result<(), Error> {
+    match action {
+        SessionAction::Pause { session } => {
+            open_history(&session, backend)?.pause(&session)?;
+            println!("recording paused");
+        }
+        SessionAction::Resume { session } => {
+            open_history(&session, backend)?.resume(&session)?;
+            println!("recording resumed");
+        }
+        SessionAction::Status { session } => {
+            let paused = open_history(&session, backend)?.is_paused(&session)?;
+            println!("{}", if paused { "paused" } else { "recording" });
+        }
+    }
     Ok(())
 }
 
-fn cmd_vacuum(yes: bool) -> Result<(), String> {
-    if !yes {
-        return Err("bd: vacuum requires --yes (this deletes all history)".to_string());
-    }
-    let path = db_path()?;
-    remove_file_if_exists(&path)?;
-    let wal_path = PathBuf::from(format!("{}-wal", path.display()));
-    let shm_path = PathBuf::from(format!("{}-shm", path.display()));
-    remove_file_if_exists(&wal_path)?;
-    remove_file_if_exists(&shm_path)?;
-    let _ = open_db()?;
+fn cmd_sessions(current: Option<&str>, json: bool, backend: &Backend) -> Result<(), Error> {
+    let sessions = open_maintenance_history(current, backend)?.sessions()?;
+    let is_current = |session: &SessionSummary| current == Some(session.session.as_str());
+
+    if json {
+        let rows: Vec<String> = sessions
+            .iter()
+            .map(|session| {
+                format!(
+                    "{{\"session_key\":\"{}\",\"last_seen_at\":{},\"last_seen_at_rfc3339\":{},\"events\":{},\"cursor_path\":{},\"undo_depth\":{},\"paused\":{},\"parent_session\":{},\"current\":{}}}",
+                    json_escape(&session.session),
+                    session.last_seen_at,
+                    format_ts(session.last_seen_at)
+                        .map_or("null".to_string(), |v| format!("\"{}\"", json_escape(&v))),
+                    session.events,
+                    session
+                        .cursor_path
+                        .as_ref()
+                        .map_or("null".to_string(), |v| format!("\"{}\"", json_escape(v))),
+                    session.undo_depth,
+                    session.paused,
+                    session
+                        .parent_session
+                        .as_ref()
+                        .map_or("null".to_string(), |v| format!("\"{}\"", json_escape(v))),
+                    is_current(session),
+                )
+            })
+            .collect();
+        println!("[{}]", rows.join(","));
+        return Ok(());
+    }
+
+    if sessions.is_empty() {
+        return Err(Error::EmptyHistory("no sessions".to_string()));
+    }
+    let home = display_home();
+    let width = sessions
+        .iter()
+        .map(|session| session.session.len())
+        .chain(["SESSION".len()])
+        .max()
+        .unwrap_or(0);
+    println!(
+        "  {:<width$}  {:<20}  {:>6}  {:>4}  CURSOR",
+        "SESSION", "LAST SEEN", "EVENTS", "UNDO"
+    );
+    for session in &sessions {
+        println!(
+            "{} {:<width$}  {:<20}  {:>6}  {:>4}  {}{}",
+            if is_current(session) { "*" } else { " " },
+            session.session,
+            format_ts(session.last_seen_at).unwrap_or_else(|| "-".to_string()),
+            session.events,
+            session.undo_depth,
+            session
+                .cursor_path
+                .as_deref()
+                .map_or("-".to_string(), |path| display_path(path, &home)),
+            if session.paused { " (paused)" } else { "" },
+        );
+    }
+    Ok(())
+}
+
+fn cmd_config(action: ConfigAction) -> Result<(), Error> {
+    let config = Config::load()?;
+    match action {
+        ConfigAction::Show => {
+            // Printed as TOML, so the output can seed a config file.
+            println!("# {}", config_file_label(&config));
+            for (key, value, source) in config.settings() {
+                println!("{key} = {} # {}", value.literal(), source.as_str());
+            }
+        }
+        ConfigAction::Validate => {
+            println!("{}", config_file_label(&config));
+        }
+    }
+    Ok(())
+}
+
+fn cmd_forget(
+    path: &str,
+    session: Option<&str>,
+    recursive: bool,
+    all_sessions: bool,
+    backend: &Backend,
+) -> Result<(), Error> {
+    let mut history = open_maintenance_history(session, backend)?;
+    let absolute = env::current_dir()
+        .map_err(|e| Error::Other(format!("cannot read current directory: {e}")))?
+        .join(path);
+    let scope = if all_sessions { None } else { session };
+    let report = history.forget(scope, &absolute.to_string_lossy(), recursive)?;
+    println!("removed: {} events", report.events);
+    for path in &report.paths {
+        println!("  {path}");
+    }
+    println!("sessions repaired: {}", report.sessions_repaired);
+    println!("cancel entries dropped: {}", report.undo_removed);
     Ok(())
 }
 
-fn remove_file_if_exists(path: &Path) -> Result<(), String> {
-    match std::fs::remove_file(path) {
-        Ok(()) => Ok(()),
-        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
-        Err(err) => Err(format!("bd: db error: {err}")),
+fn cmd_prune(
+    session: Option<&str>,
+    all: bool,
+    dry_run: bool,
+    backend: &Backend,
+) -> Result<(), Error> {
+    let mut history = open_maintenance_history(session, backend)?;
+    let scope = if all { None } else { session };
+    let report = history.prune(scope, dry_run)?;
+    let verb = if dry_run { "would remove" } else { "removed" };
+    println!("{verb}: {} events", report.events);
+    for (path, events) in &report.paths {
+        println!("  {path} ({events})");
     }
+    println!("kept (still referenced): {} events", report.kept);
+    Ok(())
 }
 
-fn file_size(path: &Path) -> Option<u64> {
-    std::fs::metadata(path).ok().map(|meta| meta.len())
+/// Opens the history for `forget`, `prune` and `sessions`, which may span every session and
+/// so only need `--session` to find an ephemeral snapshot.
+fn open_maintenance_history(session: Option<&str>, backend: &Backend) -> Result<History, Error> {
+    match (session, backend.ephemeral) {
+        (Some(session), true) => History::ephemeral(session),
+        (None, true) => Err(Error::Usage(
+            "--ephemeral needs --session to find the snapshot".to_string(),
+        )),
+        (_, false) => backend.open(),
+    }
+}
+
+fn cmd_check_ignore(path: &str) -> Result<(), Error> {
+    let absolute = env::current_dir()
+        .map_err(|e| Error::Other(format!("cannot read current directory: {e}")))?
+        .join(path);
+    match Config::load()?.check_ignore(&absolute.to_string_lossy())? {
+        Some(rule) => {
+            let verdict = if rule.exclude { "excluded" } else { "included" };
+            println!("{path}: {verdict} by {:?} ({})", rule.pattern, rule.origin);
+        }
+        None => println!("{path}: recorded (no rule matches)"),
+    }
+    Ok(())
+}
+
+fn config_file_label(config: &Config) -> String {
+    match (&config.path, config.loaded) {
+        (Some(path), true) => format!("{} (ok)", path.display()),
+        (Some(path), false) => format!("{} (not found, using defaults)", path.display()),
+        (None, _) => "built-in defaults".to_string(),
+    }
+}
+
+fn config_json(config: &Config) -> String {
+    let path = match &config.path {
+        Some(path) => format!("\"{}\"", json_escape(&path.to_string_lossy())),
+        None => "null".to_string(),
+    };
+    let mut fields = vec![
+        format!("\"path\":{path}"),
+        format!("\"loaded\":{}", config.loaded),
+    ];
+    for (key, value, source) in config.settings() {
+        fields.push(format!(
+            "\"{key}\":{{\"value\":{},\"source\":\"{}\"}}",
+            value.literal(),
+            source.as_str()
+        ));
+    }
+    format!("{{{}}}", fields.join(","))
+}
+
+fn cmd_vacuum(yes: bool, backend: &Backend) -> Result<(), Error> {
+    if !yes {
+        return Err(Error::Usage(
+            "vacuum requires --yes (this deletes all history)".to_string(),
+        ));
+    }
+    backend.require_database()?;
+    bd_core::reset(&backend.db_path()?)
 }
 
 fn format_bytes(bytes: u64) -> String {
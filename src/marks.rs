@@ -0,0 +1,53 @@
+//! Named bookmarks, shared by every session.
+
+use crate::db::current_ts;
+use crate::{Error, History};
+use std::path::Path;
+
+pub struct Mark {
+    pub name: String,
+    pub path: String,
+}
+
+impl History {
+    /// Points mark `name` at `path`, replacing any previous target.
+    pub fn set_mark(&mut self, name: &str, path: &str) -> Result<(), Error> {
+        validate_mark_name(name)?;
+        if !Path::new(path).is_dir() {
+            return Err(Error::MissingDirectory(
+                "pwd is not a directory".to_string(),
+            ));
+        }
+        self.store.set_mark(name, path, current_ts())
+    }
+
+    /// Returns the directory mark `name` points to, which must still exist.
+    pub fn mark(&self, name: &str) -> Result<String, Error> {
+        match self.store.mark(name)? {
+            Some(path) if Path::new(&path).is_dir() => Ok(path),
+            Some(path) => Err(Error::MissingDirectory(format!(
+                "mark {name} points to a missing directory: {path}"
+            ))),
+            None => Err(Error::EmptyHistory(format!("no mark named {name}"))),
+        }
+    }
+
+    /// All marks ordered by name, including those whose directory is gone.
+    pub fn marks(&self) -> Result<Vec<Mark>, Error> {
+        self.store.marks()
+    }
+
+    pub fn remove_mark(&mut self, name: &str) -> Result<(), Error> {
+        if !self.store.remove_mark(name)? {
+            return Err(Error::EmptyHistory(format!("no mark named {name}")));
+        }
+        Ok(())
+    }
+}
+
+fn validate_mark_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.starts_with('-') || name.chars().any(char::is_whitespace) {
+        return Err(Error::Usage(format!("invalid mark name: {name:?}")));
+    }
+    Ok(())
+}
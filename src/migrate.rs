@@ -0,0 +1,323 @@
+//! Schema migrations keyed on `PRAGMA user_version`.
+//!
+//! Each step runs in its own `BEGIN IMMEDIATE` transaction together with the version bump,
+//! so a failed step leaves the database at the previous version and concurrent shells never
+//! apply the same step twice. Databases created before versioning report version 0; the
+//! early steps therefore tolerate tables and columns that already exist.
+
+use crate::Error;
+use rusqlite::{Connection, TransactionBehavior};
+
+type Step = fn(&Connection) -> Result<(), Error>;
+
+/// Ordered migrations; step `i` upgrades the schema from version `i` to `i + 1`.
+const MIGRATIONS: &[Step] = &[
+    base_tables,
+    events_host,
+    marks_table,
+    events_real_path,
+    sessions_paused,
+    dirs_table,
+    sessions_parent,
+    sessions_parent_min_id,
+];
+
+/// Schema version written by this build.
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// Brings the schema up to [`SCHEMA_VERSION`]. When it is already current this is a single
+/// pragma read and no DDL runs.
+pub(crate) fn migrate(conn: &mut Connection) -> Result<(), Error> {
+    let version = user_version(conn)?;
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+    check_supported(version)?;
+
+    loop {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        // Another process may have migrated while we waited for the write lock.
+        let version = user_version(&tx)?;
+        check_supported(version)?;
+        if version == SCHEMA_VERSION {
+            return Ok(());
+        }
+        MIGRATIONS[version as usize](&tx)?;
+        tx.pragma_update(None, "user_version", version + 1)?;
+        tx.commit()?;
+    }
+}
+
+pub(crate) fn user_version(conn: &Connection) -> Result<i64, Error> {
+    Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+fn check_supported(version: i64) -> Result<(), Error> {
+    if version > SCHEMA_VERSION {
+        return Err(Error::SchemaTooNew {
+            found: version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    Ok(())
+}
+
+/// v1: the tables of the first release. `last_seen_at` and `created_at` were added after
+/// it shipped, so unversioned databases may still lack them.
+fn base_tables(conn: &Connection) -> Result<(), Error> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS events (
+           id INTEGER PRIMARY KEY AUTOINCREMENT,
+           session_key TEXT NOT NULL DEFAULT '',
+           path TEXT NOT NULL,
+           ts INTEGER NOT NULL
+         );
+         CREATE INDEX IF NOT EXISTS idx_events_session_id ON events(session_key, id);
+         CREATE INDEX IF NOT EXISTS idx_events_ts ON events(ts);
+         CREATE TABLE IF NOT EXISTS sessions (
+           session_key TEXT PRIMARY KEY,
+           cursor_id INTEGER NOT NULL,
+           last_bd_delta INTEGER NOT NULL DEFAULT 0,
+           last_bd_from_id INTEGER NOT NULL DEFAULT 0,
+           last_bd_to_id INTEGER NOT NULL DEFAULT 0,
+           last_bd_armed INTEGER NOT NULL DEFAULT 0
+         );
+         CREATE TABLE IF NOT EXISTS undo_moves (
+           id INTEGER PRIMARY KEY AUTOINCREMENT,
+           session_key TEXT NOT NULL,
+           from_id INTEGER NOT NULL,
+           to_id INTEGER NOT NULL
+         );
+         CREATE INDEX IF NOT EXISTS idx_undo_moves_session_id ON undo_moves(session_key, id);
+         CREATE TABLE IF NOT EXISTS meta (
+           key TEXT PRIMARY KEY,
+           value INTEGER NOT NULL
+         );",
+    )?;
+    ensure_column(
+        conn,
+        "sessions",
+        "last_seen_at",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+    ensure_column(
+        conn,
+        "undo_moves",
+        "created_at",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+    Ok(())
+}
+
+/// v2: `events.host`, for `--scope host`.
+fn events_host(conn: &Connection) -> Result<(), Error> {
+    ensure_column(conn, "events", "host", "TEXT NOT NULL DEFAULT ''")?;
+    conn.execute_batch("CREATE INDEX IF NOT EXISTS idx_events_host_id ON events(host, id);")?;
+    Ok(())
+}
+
+/// v3: the `marks` table.
+fn marks_table(conn: &Connection) -> Result<(), Error> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS marks (
+           name TEXT PRIMARY KEY,
+           path TEXT NOT NULL,
+           created_at INTEGER NOT NULL DEFAULT 0
+         );",
+    )?;
+    Ok(())
+}
+
+/// v4: `events.real_path`, the canonical form of `path`.
+fn events_real_path(conn: &Connection) -> Result<(), Error> {
+    ensure_column(conn, "events", "real_path", "TEXT NOT NULL DEFAULT ''")
+}
+
+/// v5: `sessions.paused`, set by `session pause`.
+fn sessions_paused(conn: &Connection) -> Result<(), Error> {
+    ensure_column(conn, "sessions", "paused", "INTEGER NOT NULL DEFAULT 0")
+}
+
+/// v6: `dirs`, which stores each directory once; `events` refers to it by id instead of
+/// repeating `path` and `real_path` on every row. Rows recorded before v4 get their `path`
+/// as the canonical directory. Dropping the columns leaves free pages behind until the
+/// next `VACUUM` (`optimize`).
+fn dirs_table(conn: &Connection) -> Result<(), Error> {
+    conn.execute_batch(
+        "CREATE TABLE dirs (
+           id INTEGER PRIMARY KEY,
+           path TEXT NOT NULL UNIQUE
+         );
+         INSERT OR IGNORE INTO dirs (path) SELECT path FROM events;
+         INSERT OR IGNORE INTO dirs (path) SELECT real_path FROM events WHERE real_path != '';
+         ALTER TABLE events ADD COLUMN dir_id INTEGER NOT NULL DEFAULT 0;
+         ALTER TABLE events ADD COLUMN real_dir_id INTEGER NOT NULL DEFAULT 0;
+         UPDATE events SET
+           dir_id = (SELECT id FROM dirs WHERE dirs.path = events.path),
+           real_dir_id = (
+             SELECT id FROM dirs WHERE dirs.path = COALESCE(NULLIF(events.real_path, ''), events.path)
+           );
+         ALTER TABLE events DROP COLUMN path;
+         ALTER TABLE events DROP COLUMN real_path;",
+    )?;
+    Ok(())
+}
+
+/// v7: `sessions.parent_session` and `sessions.parent_max_id`, linking a session to the
+/// history it inherited from the shell that started it.
+fn sessions_parent(conn: &Connection) -> Result<(), Error> {
+    ensure_column(
+        conn,
+        "sessions",
+        "parent_session",
+        "TEXT NOT NULL DEFAULT ''",
+    )?;
+    ensure_column(
+        conn,
+        "sessions",
+        "parent_max_id",
+        "INTEGER NOT NULL DEFAULT 0",
+    )
+}
+
+/// v8: `sessions.parent_min_id`, the oldest event a session inherits. Links made before
+/// keep 0 and inherit everything up to `parent_max_id`, as they did.
+fn sessions_parent_min_id(conn: &Connection) -> Result<(), Error> {
+    ensure_column(
+        conn,
+        "sessions",
+        "parent_min_id",
+        "INTEGER NOT NULL DEFAULT 0",
+    )
+}
+
+/// Adds `column` unless an unversioned database already has it.
+fn ensure_column(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let name: String = row.get(1)?;
+        if name == column {
+            return Ok(());
+        }
+    }
+    conn.execute(
+        &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+        [],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = conn
+            .prepare(&format!("PRAGMA table_info({table})"))
+            .unwrap();
+        let names = stmt
+            .query_map([], |row| row.get::<_, String>(1))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .unwrap();
+        names
+    }
+
+    #[test]
+    fn a_new_database_is_migrated_to_the_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+
+        assert_eq!(user_version(&conn).unwrap(), SCHEMA_VERSION);
+        let events = columns(&conn, "events");
+        for column in ["host", "dir_id", "real_dir_id"] {
+            assert!(events.contains(&column.to_string()), "{column}");
+        }
+        assert!(!events.contains(&"path".to_string()));
+        let sessions = columns(&conn, "sessions");
+        for column in [
+            "last_seen_at",
+            "paused",
+            "parent_session",
+            "parent_min_id",
+            "parent_max_id",
+        ] {
+            assert!(sessions.contains(&column.to_string()), "{column}");
+        }
+        assert!(columns(&conn, "marks").contains(&"name".to_string()));
+
+        // A current database is left alone.
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn an_unversioned_database_keeps_its_history() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // The first release's tables, before `last_seen_at` and versioning existed.
+        conn.execute_batch(
+            "CREATE TABLE events (
+               id INTEGER PRIMARY KEY AUTOINCREMENT,
+               session_key TEXT NOT NULL DEFAULT '',
+               path TEXT NOT NULL,
+               ts INTEGER NOT NULL
+             );
+             CREATE TABLE sessions (
+               session_key TEXT PRIMARY KEY,
+               cursor_id INTEGER NOT NULL
+             );
+             INSERT INTO events (session_key, path, ts) VALUES
+               ('s', '/a', 1), ('s', '/b', 2), ('s', '/a', 3);
+             INSERT INTO sessions (session_key, cursor_id) VALUES ('s', 3);",
+        )
+        .unwrap();
+        migrate(&mut conn).unwrap();
+
+        assert_eq!(user_version(&conn).unwrap(), SCHEMA_VERSION);
+        let mut stmt = conn
+            .prepare(
+                "SELECT dir.path, real_dir.path FROM events
+                 JOIN dirs dir ON dir.id = events.dir_id
+                 JOIN dirs real_dir ON real_dir.id = events.real_dir_id
+                 ORDER BY events.id",
+            )
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+        // Rows without a canonical path fall back to the recorded one.
+        let expected = [("/a", "/a"), ("/b", "/b"), ("/a", "/a")]
+            .map(|(path, real)| (path.to_string(), real.to_string()));
+        assert_eq!(rows, expected);
+        let dirs: i64 = conn
+            .query_row("SELECT COUNT(*) FROM dirs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(dirs, 2);
+        let cursor: i64 = conn
+            .query_row("SELECT cursor_id FROM sessions", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(cursor, 3);
+    }
+
+    #[test]
+    fn a_newer_schema_is_refused() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        assert!(matches!(
+            migrate(&mut conn),
+            Err(Error::SchemaTooNew { found, supported })
+                if found == SCHEMA_VERSION + 1 && supported == SCHEMA_VERSION
+        ));
+    }
+}
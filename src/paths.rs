@@ -0,0 +1,205 @@
+//! Path handling: normalization, the symlink policy, ancestor lookup and matching.
+
+use crate::Error;
+use clap::ValueEnum;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum PathPolicy {
+    /// Keep the path as the shell reports it (symlinks preserved), normalized lexically.
+    Logical,
+    /// Record the fully resolved path, with symlinks followed.
+    Physical,
+}
+
+/// Returns the path to show for `pwd` under `policy`, plus its canonical path. The
+/// canonical path is what dedup, matching and `--unique` compare.
+pub(crate) fn resolve_record_path(
+    pwd: &str,
+    policy: PathPolicy,
+) -> Result<(String, String), Error> {
+    let real_path = std::fs::canonicalize(pwd)
+        .map_err(|e| Error::MissingDirectory(format!("cannot resolve {pwd}: {e}")))?
+        .to_string_lossy()
+        .into_owned();
+    let display = match policy {
+        PathPolicy::Logical => normalize_logical(pwd)?,
+        PathPolicy::Physical => real_path.clone(),
+    };
+    Ok((display, real_path))
+}
+
+/// Lexically normalizes an absolute path the way `cd -L` does: repeated and trailing
+/// slashes and `.` components are dropped, and `..` removes the previous component.
+pub(crate) fn normalize_logical(pwd: &str) -> Result<String, Error> {
+    let path = Path::new(pwd);
+    if !path.is_absolute() {
+        return Err(Error::Usage("pwd must be an absolute path".to_string()));
+    }
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    Ok(normalized.to_string_lossy().into_owned())
+}
+
+/// The ancestor of `pwd` that `bd u TARGET` goes to. The nearest ancestor named exactly
+/// `target` wins; otherwise an all-digit `target` counts levels up, and anything else
+/// picks the nearest ancestor whose name starts with it. So `bd u 2024` goes to an
+/// ancestor named `2024` when there is one, and `bd u 2` climbs two levels unless an
+/// ancestor is named `2`.
+pub(crate) fn resolve_ancestor(pwd: &Path, target: &str) -> Result<PathBuf, Error> {
+    if target.is_empty() {
+        return Err(Error::Usage("usage: bd u [NAME|N]".to_string()));
+    }
+    let name_of = |path: &Path| {
+        path.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+    };
+    let mut ancestors = pwd.ancestors().skip(1);
+    if let Some(path) = ancestors
+        .clone()
+        .find(|path| name_of(path).as_deref() == Some(target))
+    {
+        return Ok(path.to_path_buf());
+    }
+
+    if target.bytes().all(|b| b.is_ascii_digit()) {
+        let levels: usize = target
+            .parse()
+            .map_err(|_| Error::Usage("usage: bd u [NAME|N]".to_string()))?;
+        if levels == 0 {
+            return Err(Error::Usage("usage: bd u [NAME|N]".to_string()));
+        }
+        return ancestors
+            .nth(levels - 1)
+            .map(Path::to_path_buf)
+            .ok_or_else(|| Error::Usage(format!("cannot go up {levels} levels")));
+    }
+    ancestors
+        .find(|path| name_of(path).is_some_and(|name| name.starts_with(target)))
+        .map(Path::to_path_buf)
+        .ok_or_else(|| Error::Usage(format!("no ancestor named {target}")))
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum MatchMode {
+    /// Glob if the pattern contains `*`, `?` or `[`, substring otherwise.
+    Auto,
+    Substring,
+    Glob,
+    Regex,
+}
+
+pub(crate) enum PathMatcher {
+    Substring(String),
+    /// Patterns without a `/` are matched against the last path component only.
+    Glob(glob::Pattern, bool),
+    Regex(regex::Regex),
+}
+
+impl PathMatcher {
+    pub(crate) fn new(pattern: &str, mode: MatchMode) -> Result<Self, Error> {
+        let mode = match mode {
+            MatchMode::Auto if pattern.contains(['*', '?', '[']) => MatchMode::Glob,
+            MatchMode::Auto => MatchMode::Substring,
+            other => other,
+        };
+        match mode {
+            MatchMode::Glob => glob::Pattern::new(pattern)
+                .map(|glob| PathMatcher::Glob(glob, pattern.contains('/')))
+                .map_err(|e| Error::Usage(format!("invalid glob: {e}"))),
+            MatchMode::Regex => regex::Regex::new(pattern)
+                .map(PathMatcher::Regex)
+                .map_err(|e| Error::Usage(format!("invalid regex: {e}"))),
+            _ => Ok(PathMatcher::Substring(pattern.to_string())),
+        }
+    }
+
+    pub(crate) fn is_match(&self, path: &str) -> bool {
+        match self {
+            PathMatcher::Substring(needle) => path.contains(needle.as_str()),
+            PathMatcher::Glob(glob, true) => glob.matches(path),
+            PathMatcher::Glob(glob, false) => Path::new(path)
+                .file_name()
+                .is_some_and(|name| glob.matches(&name.to_string_lossy())),
+            PathMatcher::Regex(re) => re.is_match(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_ancestor_counts_levels() {
+        let pwd = Path::new("/home/u/src/proj/lib");
+        assert_eq!(
+            resolve_ancestor(pwd, "1").unwrap(),
+            Path::new("/home/u/src/proj")
+        );
+        assert_eq!(resolve_ancestor(pwd, "3").unwrap(), Path::new("/home/u"));
+        assert_eq!(resolve_ancestor(pwd, "5").unwrap(), Path::new("/"));
+        for target in ["0", "6", ""] {
+            assert!(
+                matches!(resolve_ancestor(pwd, target), Err(Error::Usage(_))),
+                "{target:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_ancestor_prefers_an_exact_name_over_a_nearer_prefix() {
+        let pwd = Path::new("/p/src/q/srcx/r");
+        assert_eq!(resolve_ancestor(pwd, "src").unwrap(), Path::new("/p/src"));
+        assert_eq!(
+            resolve_ancestor(pwd, "sr").unwrap(),
+            Path::new("/p/src/q/srcx")
+        );
+        assert!(matches!(
+            resolve_ancestor(pwd, "nomatch"),
+            Err(Error::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn resolve_ancestor_matches_a_numeric_name_before_counting() {
+        let pwd = Path::new("/notes/2024/jan/week1");
+        assert_eq!(
+            resolve_ancestor(pwd, "2024").unwrap(),
+            Path::new("/notes/2024")
+        );
+        assert_eq!(
+            resolve_ancestor(pwd, "2").unwrap(),
+            Path::new("/notes/2024")
+        );
+        assert_eq!(resolve_ancestor(pwd, "3").unwrap(), Path::new("/notes"));
+        // Digits never prefix-match a name.
+        assert!(matches!(resolve_ancestor(pwd, "20"), Err(Error::Usage(_))));
+    }
+
+    #[test]
+    fn resolve_ancestor_skips_the_directory_itself() {
+        let pwd = Path::new("/p/src/q/src");
+        assert_eq!(resolve_ancestor(pwd, "src").unwrap(), Path::new("/p/src"));
+    }
+
+    #[test]
+    fn normalize_logical_resolves_dots_and_slashes() {
+        assert_eq!(normalize_logical("/a//b/./c/../d/").unwrap(), "/a/b/d");
+        assert_eq!(normalize_logical("/../a").unwrap(), "/a");
+        assert_eq!(normalize_logical("/").unwrap(), "/");
+    }
+
+    #[test]
+    fn normalize_logical_rejects_relative_paths() {
+        assert!(matches!(normalize_logical("a/b"), Err(Error::Usage(_))));
+    }
+}
@@ -0,0 +1,78 @@
+//! Frecency-ranked lookup across every session's history.
+
+use crate::db::current_ts;
+use crate::{Error, History};
+use std::path::Path;
+
+/// A directory matching a query, with its frecency score.
+pub struct RankedPath {
+    pub score: f64,
+    pub path: String,
+}
+
+impl History {
+    /// Ranks existing directories whose path contains `terms` in order, highest score
+    /// first. `exclude` drops one path, typically the current directory. Records still
+    /// waiting in the spool are stored first so they are ranked.
+    pub fn query(
+        &mut self,
+        terms: &[String],
+        exclude: Option<&str>,
+    ) -> Result<Vec<RankedPath>, Error> {
+        self.replay_spool()?;
+        let terms: Vec<String> = terms.iter().map(|term| term.to_lowercase()).collect();
+        let now = current_ts();
+
+        let mut ranked: Vec<RankedPath> = Vec::new();
+        for stats in self.store.path_stats()? {
+            if exclude == Some(stats.path.as_str()) || !query_matches(&stats.path, &terms) {
+                continue;
+            }
+            if !Path::new(&stats.path).is_dir() {
+                continue;
+            }
+            ranked.push(RankedPath {
+                score: frecency(stats.visits, now - stats.last_ts),
+                path: stats.path,
+            });
+        }
+
+        ranked.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        Ok(ranked)
+    }
+}
+
+/// Terms must appear in order (case-insensitively), and the last one must fall within
+/// the last path component, so `bd q foo` prefers `.../foo` over `.../foo/bar`.
+fn query_matches(path: &str, terms: &[String]) -> bool {
+    let Some(last) = terms.last() else {
+        return true;
+    };
+    let path = path.to_lowercase();
+    let mut rest = path.as_str();
+    for term in terms {
+        match rest.find(term.as_str()) {
+            Some(pos) => rest = &rest[pos + term.len()..],
+            None => return false,
+        }
+    }
+    let last_component = path.rsplit('/').next().unwrap_or_default();
+    last_component.contains(last.as_str())
+}
+
+fn frecency(visits: i64, age_secs: i64) -> f64 {
+    let weight = if age_secs < 60 * 60 {
+        4.0
+    } else if age_secs < 24 * 60 * 60 {
+        2.0
+    } else if age_secs < 7 * 24 * 60 * 60 {
+        0.5
+    } else {
+        0.25
+    };
+    visits as f64 * weight
+}
@@ -0,0 +1,207 @@
+//! Append-only spool for directory changes recorded while the database was locked.
+//!
+//! `record` runs from a prompt hook that cannot wait long, so when the database stays
+//! locked past the busy timeout the change is appended to `<db>-spool` instead of being
+//! dropped. The next command that reaches the database replays the spool into `events`
+//! in order and empties it. Both sides hold an exclusive `flock` on the spool file.
+
+use crate::db::current_ts;
+use crate::history::{store_record, Record};
+use crate::ignore::matching_rule;
+use crate::paths::resolve_record_path;
+use crate::store::{escape, unescape};
+use crate::{Error, History, PathPolicy};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// The spool that belongs to the database at `db`.
+pub fn spool_path(db: &Path) -> PathBuf {
+    PathBuf::from(format!("{}-spool", db.display()))
+}
+
+/// Appends a `record` of `pwd` for `session` to the spool of the database at `db`, to be
+/// stored by the next command that can open it.
+pub fn spool_record(db: &Path, session: &str, pwd: &str, policy: PathPolicy) -> Result<(), Error> {
+    if !Path::new(pwd).is_dir() {
+        return Err(Error::MissingDirectory(
+            "pwd is not a directory".to_string(),
+        ));
+    }
+    // Resolved now: the symlinks may point elsewhere by the time the spool is replayed.
+    let (logical, real_path) = resolve_record_path(pwd, PathPolicy::Logical)?;
+    let policy = match policy {
+        PathPolicy::Logical => "logical",
+        PathPolicy::Physical => "physical",
+    };
+    let line = format!(
+        "{}\t{policy}\t{}\t{}\t{}\n",
+        current_ts(),
+        escape(session),
+        escape(&logical),
+        escape(&real_path)
+    );
+
+    let path = spool_path(db);
+    let mut file = {
+        use std::os::unix::fs::OpenOptionsExt;
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(|err| Error::Io(format!("{}: {err}", path.display())))?
+    };
+    lock(&file, true)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Number of records waiting in the spool of the database at `db`.
+pub(crate) fn spooled(db: &Path) -> usize {
+    fs::read_to_string(spool_path(db))
+        .map(|text| text.lines().filter_map(parse_line).count())
+        .unwrap_or(0)
+}
+
+struct Spooled {
+    ts: i64,
+    physical: bool,
+    session: String,
+    logical: String,
+    real_path: String,
+}
+
+fn parse_line(line: &str) -> Option<Spooled> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let [ts, policy, session, logical, real_path] = fields[..] else {
+        return None;
+    };
+    Some(Spooled {
+        ts: ts.parse().ok()?,
+        physical: match policy {
+            "logical" => false,
+            "physical" => true,
+            _ => return None,
+        },
+        session: unescape(session),
+        logical: unescape(logical),
+        real_path: unescape(real_path),
+    })
+}
+
+impl History {
+    /// Stores the spooled records, oldest first, and empties the spool. Does nothing for
+    /// an ephemeral store, or while another process holds the spool.
+    pub(crate) fn replay_spool(&mut self) -> Result<(), Error> {
+        let Some(path) = self.store.as_sqlite().map(|store| spool_path(store.path())) else {
+            return Ok(());
+        };
+        if fs::metadata(&path).map_or(true, |meta| meta.len() == 0) {
+            return Ok(());
+        }
+        let mut file = match OpenOptions::new().read(true).write(true).open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(Error::Io(format!("{}: {err}", path.display()))),
+        };
+        if !lock(&file, false)? {
+            return Ok(());
+        }
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+
+        let records: Vec<(Spooled, bool)> = text
+            .lines()
+            .filter_map(parse_line)
+            .map(|spooled| {
+                // A broken `.bdignore` fails the live `record`; here it must not keep every
+                // later command from running, so the record is kept instead.
+                let ignored = matching_rule(self.config(), &spooled.logical, &spooled.real_path)
+                    .ok()
+                    .flatten()
+                    .is_some_and(|rule| rule.exclude);
+                (spooled, ignored)
+            })
+            .collect();
+        let keep = self.config().events_per_session.value as usize;
+        self.atomic(|store| {
+            for (spooled, ignored) in &records {
+                let display = if spooled.physical {
+                    &spooled.real_path
+                } else {
+                    &spooled.logical
+                };
+                let record = Record {
+                    session: &spooled.session,
+                    display,
+                    real_path: &spooled.real_path,
+                    ignored: *ignored,
+                    ts: spooled.ts,
+                };
+                store_record(store, &record, keep)?;
+            }
+            Ok(())
+        })?;
+        file.set_len(0)?;
+        Ok(())
+    }
+}
+
+/// Takes an exclusive `flock` on `file`, released when it is closed. Without `wait`,
+/// returns `false` instead of blocking when another process holds it.
+pub(crate) fn lock(file: &File, wait: bool) -> Result<bool, Error> {
+    let operation = if wait {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_EX | libc::LOCK_NB
+    };
+    // SAFETY: the descriptor is open for the duration of the call.
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    if !wait && err.kind() == std::io::ErrorKind::WouldBlock {
+        return Ok(false);
+    }
+    Err(err.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::tests::{listed, TempDirs};
+    use crate::store::SqliteStore;
+    use crate::HistoryView;
+
+    #[test]
+    fn spooled_records_are_replayed_in_order() {
+        let dirs = TempDirs::new("spool", &["a", "b"]);
+        let db = dirs.root.join("bd.sqlite3");
+        for name in ["a", "b"] {
+            spool_record(&db, "s", &dirs.path(name), PathPolicy::Logical).unwrap();
+        }
+        assert_eq!(spooled(&db), 2);
+
+        let mut history = History::with_store(Box::new(SqliteStore::open(&db).unwrap()));
+        history.replay_spool().unwrap();
+        assert_eq!(spooled(&db), 0);
+        assert_eq!(
+            listed(&mut history, &HistoryView::session("s")),
+            [dirs.path("a")]
+        );
+    }
+
+    #[test]
+    fn forget_sees_spooled_records() {
+        let dirs = TempDirs::new("spool-forget", &["a"]);
+        let db = dirs.root.join("bd.sqlite3");
+        spool_record(&db, "s", &dirs.path("a"), PathPolicy::Logical).unwrap();
+
+        let mut history = History::with_store(Box::new(SqliteStore::open(&db).unwrap()));
+        let report = history.forget(None, &dirs.path("a"), false).unwrap();
+        assert_eq!(report.events, 1);
+        assert_eq!(spooled(&db), 0);
+    }
+}
@@ -0,0 +1,649 @@
+//! An in-memory backend, optionally snapshotted to a file between invocations.
+//!
+//! `bd-core` is a short-lived process, so `--ephemeral` sessions keep their state in a
+//! snapshot under `$XDG_RUNTIME_DIR` (a per-user tmpfs that is cleared on logout), or a
+//! private directory in the temp directory without one, rather than in the database.
+
+use super::{
+    EventFilter, EventRow, EventScan, LastMove, NewEvent, ParentLink, PathStats, SessionRow, Store,
+    UndoMove,
+};
+use crate::marks::Mark;
+use crate::{Config, Error};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT_HEADER: &str = "bd-ephemeral 1";
+
+#[derive(Clone)]
+struct Event {
+    session: String,
+    dir_id: i64,
+    real_dir_id: i64,
+    ts: i64,
+    host: String,
+}
+
+#[derive(Clone, Default)]
+struct Session {
+    cursor_id: i64,
+    last_bd_from_id: i64,
+    last_bd_to_id: i64,
+    last_seen_at: i64,
+    paused: bool,
+    /// Empty unless the session was started from another one.
+    parent_session: String,
+    parent_min_id: i64,
+    parent_max_id: i64,
+}
+
+#[derive(Clone)]
+struct Undo {
+    session: String,
+    from_id: i64,
+    to_id: i64,
+}
+
+/// Directories interned as in the database's `dirs` table. Rotation leaves unused
+/// entries behind, as the database does until its periodic cleanup; a snapshot only keeps
+/// the ones its events refer to.
+#[derive(Clone, Default)]
+struct Dirs {
+    paths: HashMap<i64, String>,
+    ids: HashMap<String, i64>,
+    next_id: i64,
+}
+
+impl Dirs {
+    fn intern(&mut self, path: &str) -> i64 {
+        if let Some(&id) = self.ids.get(path) {
+            return id;
+        }
+        self.next_id += 1;
+        self.paths.insert(self.next_id, path.to_string());
+        self.ids.insert(path.to_string(), self.next_id);
+        self.next_id
+    }
+
+    fn path(&self, id: i64) -> &str {
+        &self.paths[&id]
+    }
+
+    /// Drops every entry that none of `events` refers to.
+    fn retain_used(&mut self, events: &BTreeMap<i64, Event>) {
+        let used: HashSet<i64> = events
+            .values()
+            .flat_map(|event| [event.dir_id, event.real_dir_id])
+            .collect();
+        self.paths.retain(|id, _| used.contains(id));
+        self.ids.retain(|_, id| used.contains(id));
+    }
+}
+
+#[derive(Clone, Default)]
+struct State {
+    dirs: Dirs,
+    events: BTreeMap<i64, Event>,
+    next_event_id: i64,
+    sessions: HashMap<String, Session>,
+    undo_moves: BTreeMap<i64, Undo>,
+    next_undo_id: i64,
+    marks: BTreeMap<String, String>,
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    state: State,
+    /// State as of `begin`, restored by `rollback`.
+    saved: Option<State>,
+    snapshot: Option<PathBuf>,
+    dirty: bool,
+}
+
+impl MemoryStore {
+    /// An empty store that lives only as long as the process.
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    /// Loads the snapshot at `path` if it exists, and writes it back after every
+    /// committed change.
+    pub fn with_snapshot(path: &Path) -> Result<Self, Error> {
+        let state = match fs::read_to_string(path) {
+            Ok(text) => parse_snapshot(&text).ok_or_else(|| {
+                Error::Corrupt(format!("unreadable snapshot: {}", path.display()))
+            })?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => State::default(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(MemoryStore {
+            state,
+            saved: None,
+            snapshot: Some(path.to_path_buf()),
+            dirty: false,
+        })
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        let Some(path) = &self.snapshot else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            create_private_dir(parent)?;
+        }
+        let tmp = path.with_extension("tmp");
+        let mut file = private_file(&tmp)?;
+        file.write_all(format_snapshot(&self.state).as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    fn changed(&mut self) -> &mut State {
+        self.dirty = true;
+        &mut self.state
+    }
+}
+
+impl Store for MemoryStore {
+    fn begin(&mut self) -> Result<(), Error> {
+        self.saved = Some(self.state.clone());
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), Error> {
+        self.saved = None;
+        if self.dirty {
+            self.save()?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<(), Error> {
+        if let Some(saved) = self.saved.take() {
+            self.state = saved;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn cleanup(&mut self, _session: &str, _now: i64, _config: &Config) -> Result<(), Error> {
+        // Snapshots live no longer than the login session; nothing ages out.
+        Ok(())
+    }
+
+    fn insert_event(&mut self, event: &NewEvent<'_>) -> Result<i64, Error> {
+        let state = self.changed();
+        state.next_event_id += 1;
+        let id = state.next_event_id;
+        let dir_id = state.dirs.intern(event.path);
+        let real_dir_id = state.dirs.intern(event.real_path);
+        state.events.insert(
+            id,
+            Event {
+                session: event.session.to_string(),
+                dir_id,
+                real_dir_id,
+                ts: event.ts,
+                host: event.host.to_string(),
+            },
+        );
+        Ok(id)
+    }
+
+    fn event(&self, id: i64) -> Result<Option<EventRow>, Error> {
+        Ok(self
+            .state
+            .events
+            .get(&id)
+            .map(|event| self.state.event_row(id, event)))
+    }
+
+    fn latest_event(&self, session: &str) -> Result<Option<EventRow>, Error> {
+        Ok(self
+            .state
+            .events
+            .iter()
+            .rev()
+            .find(|(_, event)| event.session == session)
+            .map(|(&id, event)| self.state.event_row(id, event)))
+    }
+
+    fn scan_events(
+        &self,
+        scan: &EventScan<'_>,
+        visit: &mut dyn FnMut(EventRow) -> bool,
+    ) -> Result<(), Error> {
+        let matches = |event: &Event| {
+            let in_scope = match scan.filter {
+                EventFilter::Session(session) => event.session == session,
+                EventFilter::Host(host) => event.host == host,
+                EventFilter::All => true,
+            };
+            in_scope && scan.max_ts.is_none_or(|max_ts| event.ts <= max_ts)
+        };
+        let rows: Box<dyn Iterator<Item = (&i64, &Event)>> = if scan.backward {
+            Box::new(self.state.events.range(..scan.from_id).rev())
+        } else {
+            Box::new(self.state.events.range(scan.from_id.saturating_add(1)..))
+        };
+        for (&id, event) in rows {
+            if matches(event) && !visit(self.state.event_row(id, event)) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn rotate_events(&mut self, session: &str, keep: usize) -> Result<(), Error> {
+        let state = &self.state;
+        let referenced = state
+            .sessions
+            .get(session)
+            .into_iter()
+            .flat_map(|row| [row.cursor_id, row.last_bd_from_id, row.last_bd_to_id])
+            .chain(
+                state
+                    .undo_moves
+                    .values()
+                    .filter(|undo| undo.session == session)
+                    .flat_map(|undo| [undo.from_id, undo.to_id]),
+            )
+            .filter(|&id| id != 0)
+            .min();
+        let cutoff = state
+            .events
+            .iter()
+            .rev()
+            .filter(|(_, event)| event.session == session)
+            .nth(keep.saturating_sub(1))
+            .map(|(&id, _)| id);
+
+        let (Some(cutoff), Some(referenced)) = (cutoff, referenced) else {
+            return Ok(());
+        };
+        let delete_before = cutoff.min(referenced);
+        self.changed()
+            .events
+            .retain(|&id, event| event.session != session || id >= delete_before);
+        Ok(())
+    }
+
+    fn delete_events(&mut self, ids: &[i64]) -> Result<(), Error> {
+        let state = self.changed();
+        for id in ids {
+            state.events.remove(id);
+        }
+        state.dirs.retain_used(&state.events);
+        Ok(())
+    }
+
+    fn dir_id(&self, path: &str) -> Result<Option<i64>, Error> {
+        Ok(self.state.dirs.ids.get(path).copied())
+    }
+
+    fn sessions(&self) -> Result<Vec<SessionRow>, Error> {
+        let mut rows: Vec<SessionRow> = self
+            .state
+            .sessions
+            .iter()
+            .map(|(session, row)| SessionRow {
+                session: session.clone(),
+                cursor_id: row.cursor_id,
+                last_from_id: row.last_bd_from_id,
+                last_to_id: row.last_bd_to_id,
+                last_seen_at: row.last_seen_at,
+                paused: row.paused,
+            })
+            .collect();
+        rows.sort_by(|a, b| a.session.cmp(&b.session));
+        Ok(rows)
+    }
+
+    fn cursor(&self, session: &str) -> Result<Option<i64>, Error> {
+        Ok(self.state.sessions.get(session).map(|row| row.cursor_id))
+    }
+
+    fn set_cursor(
+        &mut self,
+        session: &str,
+        cursor_id: i64,
+        last_move: Option<&LastMove>,
+        now: i64,
+    ) -> Result<(), Error> {
+        let (last_bd_from_id, last_bd_to_id) =
+            last_move.map_or((0, 0), |last| (last.from_id, last.to_id));
+        let row = self
+            .changed()
+            .sessions
+            .entry(session.to_string())
+            .or_default();
+        row.cursor_id = cursor_id;
+        row.last_bd_from_id = last_bd_from_id;
+        row.last_bd_to_id = last_bd_to_id;
+        row.last_seen_at = now;
+        Ok(())
+    }
+
+    fn touch_session(&mut self, session: &str, now: i64) -> Result<(), Error> {
+        if let Some(row) = self.changed().sessions.get_mut(session) {
+            row.last_seen_at = now;
+        }
+        Ok(())
+    }
+
+    fn set_paused(&mut self, session: &str, paused: bool, now: i64) -> Result<(), Error> {
+        let row = self
+            .changed()
+            .sessions
+            .entry(session.to_string())
+            .or_default();
+        row.paused = paused;
+        row.last_seen_at = now;
+        Ok(())
+    }
+
+    fn paused(&self, session: &str) -> Result<bool, Error> {
+        Ok(self
+            .state
+            .sessions
+            .get(session)
+            .is_some_and(|row| row.paused))
+    }
+
+    fn set_parent(&mut self, session: &str, link: &ParentLink, now: i64) -> Result<(), Error> {
+        let row = self
+            .changed()
+            .sessions
+            .entry(session.to_string())
+            .or_default();
+        row.parent_session = link.session.clone();
+        row.parent_min_id = link.min_id;
+        row.parent_max_id = link.max_id;
+        row.last_seen_at = now;
+        Ok(())
+    }
+
+    fn parent(&self, session: &str) -> Result<Option<ParentLink>, Error> {
+        Ok(self
+            .state
+            .sessions
+            .get(session)
+            .filter(|row| !row.parent_session.is_empty())
+            .map(|row| ParentLink {
+                session: row.parent_session.clone(),
+                min_id: row.parent_min_id,
+                max_id: row.parent_max_id,
+            }))
+    }
+
+    fn push_undo(
+        &mut self,
+        session: &str,
+        from_id: i64,
+        to_id: i64,
+        _now: i64,
+    ) -> Result<(), Error> {
+        let state = self.changed();
+        state.next_undo_id += 1;
+        state.undo_moves.insert(
+            state.next_undo_id,
+            Undo {
+                session: session.to_string(),
+                from_id,
+                to_id,
+            },
+        );
+        Ok(())
+    }
+
+    fn last_undo(&self, session: &str) -> Result<Option<UndoMove>, Error> {
+        Ok(self
+            .state
+            .undo_moves
+            .iter()
+            .rev()
+            .find(|(_, undo)| undo.session == session)
+            .map(|(&id, undo)| UndoMove {
+                id,
+                from_id: undo.from_id,
+                to_id: undo.to_id,
+            }))
+    }
+
+    fn undo_moves(&self, session: &str) -> Result<Vec<UndoMove>, Error> {
+        Ok(self
+            .state
+            .undo_moves
+            .iter()
+            .filter(|(_, undo)| undo.session == session)
+            .map(|(&id, undo)| UndoMove {
+                id,
+                from_id: undo.from_id,
+                to_id: undo.to_id,
+            })
+            .collect())
+    }
+
+    fn remove_undo(&mut self, id: i64) -> Result<(), Error> {
+        self.changed().undo_moves.remove(&id);
+        Ok(())
+    }
+
+    fn clear_undo(&mut self, session: &str) -> Result<(), Error> {
+        self.changed()
+            .undo_moves
+            .retain(|_, undo| undo.session != session);
+        Ok(())
+    }
+
+    fn path_stats(&self) -> Result<Vec<PathStats>, Error> {
+        let dirs = &self.state.dirs;
+        let mut by_real_dir: HashMap<i64, PathStats> = HashMap::new();
+        for event in self.state.events.values() {
+            let stats = by_real_dir
+                .entry(event.real_dir_id)
+                .or_insert_with(|| PathStats {
+                    path: dirs.path(event.dir_id).to_string(),
+                    visits: 0,
+                    last_ts: event.ts,
+                });
+            stats.visits += 1;
+            if event.ts >= stats.last_ts {
+                stats.last_ts = event.ts;
+                stats.path = dirs.path(event.dir_id).to_string();
+            }
+        }
+        Ok(by_real_dir.into_values().collect())
+    }
+
+    fn set_mark(&mut self, name: &str, path: &str, _now: i64) -> Result<(), Error> {
+        self.changed()
+            .marks
+            .insert(name.to_string(), path.to_string());
+        Ok(())
+    }
+
+    fn mark(&self, name: &str) -> Result<Option<String>, Error> {
+        Ok(self.state.marks.get(name).cloned())
+    }
+
+    fn marks(&self) -> Result<Vec<Mark>, Error> {
+        Ok(self
+            .state
+            .marks
+            .iter()
+            .map(|(name, path)| Mark {
+                name: name.clone(),
+                path: path.clone(),
+            })
+            .collect())
+    }
+
+    fn remove_mark(&mut self, name: &str) -> Result<bool, Error> {
+        Ok(self.changed().marks.remove(name).is_some())
+    }
+}
+
+impl State {
+    fn event_row(&self, id: i64, event: &Event) -> EventRow {
+        EventRow {
+            id,
+            path: self.dirs.path(event.dir_id).to_string(),
+            real_path: self.dirs.path(event.real_dir_id).to_string(),
+            real_dir_id: event.real_dir_id,
+            ts: event.ts,
+        }
+    }
+}
+
+/// One tab-separated record per line, tagged by kind: `e` events, `s` sessions, `u` undo
+/// moves, `m` marks. Text fields are escaped so paths may contain tabs and newlines.
+/// Events carry their paths rather than interned ids, which are reassigned on load.
+fn format_snapshot(state: &State) -> String {
+    let mut out = format!(
+        "{SNAPSHOT_HEADER}\nn\t{}\t{}\n",
+        state.next_event_id, state.next_undo_id
+    );
+    for (id, event) in &state.events {
+        out.push_str(&format!(
+            "e\t{id}\t{}\t{}\t{}\t{}\t{}\n",
+            escape(&event.session),
+            event.ts,
+            escape(&event.host),
+            escape(state.dirs.path(event.dir_id)),
+            escape(state.dirs.path(event.real_dir_id))
+        ));
+    }
+    for (session, row) in &state.sessions {
+        out.push_str(&format!(
+            "s\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            escape(session),
+            row.cursor_id,
+            row.last_bd_from_id,
+            row.last_bd_to_id,
+            row.last_seen_at,
+            i64::from(row.paused),
+            escape(&row.parent_session),
+            row.parent_max_id,
+            row.parent_min_id
+        ));
+    }
+    for (id, undo) in &state.undo_moves {
+        out.push_str(&format!(
+            "u\t{id}\t{}\t{}\t{}\n",
+            escape(&undo.session),
+            undo.from_id,
+            undo.to_id
+        ));
+    }
+    for (name, path) in &state.marks {
+        out.push_str(&format!("m\t{}\t{}\n", escape(name), escape(path)));
+    }
+    out
+}
+
+fn parse_snapshot(text: &str) -> Option<State> {
+    let mut lines = text.lines();
+    if lines.next()? != SNAPSHOT_HEADER {
+        return None;
+    }
+    let mut state = State::default();
+    for line in lines {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let int = |index: usize| fields.get(index)?.parse::<i64>().ok();
+        let text = |index: usize| fields.get(index).map(|field| unescape(field));
+        match *fields.first()? {
+            "n" => {
+                state.next_event_id = int(1)?;
+                state.next_undo_id = int(2)?;
+            }
+            "e" => {
+                let event = Event {
+                    session: text(2)?,
+                    ts: int(3)?,
+                    host: text(4)?,
+                    dir_id: state.dirs.intern(&text(5)?),
+                    real_dir_id: state.dirs.intern(&text(6)?),
+                };
+                state.events.insert(int(1)?, event);
+            }
+            "s" => {
+                let row = Session {
+                    cursor_id: int(2)?,
+                    last_bd_from_id: int(3)?,
+                    last_bd_to_id: int(4)?,
+                    last_seen_at: int(5)?,
+                    // Absent from snapshots written before sessions could be paused.
+                    paused: int(6).unwrap_or(0) != 0,
+                    // Absent from snapshots written before sessions had parents.
+                    parent_session: text(7).unwrap_or_default(),
+                    parent_max_id: int(8).unwrap_or(0),
+                    // Absent from snapshots written before the inherited range was capped.
+                    parent_min_id: int(9).unwrap_or(0),
+                };
+                state.sessions.insert(text(1)?, row);
+            }
+            "u" => {
+                let undo = Undo {
+                    session: text(2)?,
+                    from_id: int(3)?,
+                    to_id: int(4)?,
+                };
+                state.undo_moves.insert(int(1)?, undo);
+            }
+            "m" => {
+                state.marks.insert(text(1)?, text(2)?);
+            }
+            _ => return None,
+        }
+    }
+    Some(state)
+}
+
+pub(crate) fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+pub(crate) fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+fn create_private_dir(path: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(path)?;
+    Ok(())
+}
+
+fn private_file(path: &Path) -> Result<fs::File, Error> {
+    use std::os::unix::fs::OpenOptionsExt;
+    Ok(fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?)
+}
@@ -0,0 +1,161 @@
+//! Storage backends behind [`History`](crate::History).
+//!
+//! A [`Store`] only persists rows; the cursor, cancel and dedup rules live in `History`
+//! so every backend behaves the same. All calls between [`Store::begin`] and
+//! [`Store::commit`] must be applied atomically.
+
+mod memory;
+mod sqlite;
+
+pub use memory::MemoryStore;
+pub(crate) use memory::{escape, unescape};
+pub use sqlite::SqliteStore;
+
+use crate::marks::Mark;
+use crate::{Config, Error};
+
+/// A recorded directory change.
+#[derive(Clone)]
+pub struct EventRow {
+    pub id: i64,
+    pub path: String,
+    /// Canonical path; equal to `path` for rows recorded before it was stored.
+    pub real_path: String,
+    /// Interned id of `real_path`: events share it exactly when they share `real_path`.
+    pub real_dir_id: i64,
+    pub ts: i64,
+}
+
+pub struct NewEvent<'a> {
+    pub session: &'a str,
+    pub path: &'a str,
+    pub real_path: &'a str,
+    pub ts: i64,
+    pub host: &'a str,
+}
+
+/// Which events a scan visits.
+pub enum EventFilter<'a> {
+    Session(&'a str),
+    Host(&'a str),
+    All,
+}
+
+/// A scan over events, starting next to `from_id` (exclusive) and moving away from it.
+pub struct EventScan<'a> {
+    pub filter: EventFilter<'a>,
+    /// Newer-to-older when true, older-to-newer otherwise.
+    pub backward: bool,
+    pub from_id: i64,
+    /// Only events recorded at or before this Unix time.
+    pub max_ts: Option<i64>,
+}
+
+/// The move a session made most recently, kept on the session row.
+pub struct LastMove {
+    pub delta: i64,
+    pub from_id: i64,
+    pub to_id: i64,
+}
+
+/// A session's row: its cursor and the ids its last move refers to (0 when disarmed).
+pub struct SessionRow {
+    pub session: String,
+    pub cursor_id: i64,
+    pub last_from_id: i64,
+    pub last_to_id: i64,
+    pub last_seen_at: i64,
+    pub paused: bool,
+}
+
+/// The session a session was started from, and the range of event ids the new session
+/// inherits from it: the newest `max_back` entries its history had when the link was made.
+pub struct ParentLink {
+    pub session: String,
+    pub min_id: i64,
+    pub max_id: i64,
+}
+
+/// An entry of a session's cancel stack.
+pub struct UndoMove {
+    pub id: i64,
+    pub from_id: i64,
+    pub to_id: i64,
+}
+
+/// Visit statistics for one directory, grouped by canonical path.
+pub struct PathStats {
+    /// The path as most recently recorded.
+    pub path: String,
+    pub visits: i64,
+    pub last_ts: i64,
+}
+
+pub trait Store {
+    fn begin(&mut self) -> Result<(), Error>;
+    fn commit(&mut self) -> Result<(), Error>;
+    fn rollback(&mut self) -> Result<(), Error>;
+
+    /// Periodic retention cleanup; called outside a transaction before each command.
+    /// `config` supplies the cleanup interval and retention periods.
+    fn cleanup(&mut self, session: &str, now: i64, config: &Config) -> Result<(), Error>;
+
+    fn insert_event(&mut self, event: &NewEvent<'_>) -> Result<i64, Error>;
+    fn event(&self, id: i64) -> Result<Option<EventRow>, Error>;
+    fn latest_event(&self, session: &str) -> Result<Option<EventRow>, Error>;
+    /// Calls `visit` with each matching event until it returns `false`.
+    fn scan_events(
+        &self,
+        scan: &EventScan<'_>,
+        visit: &mut dyn FnMut(EventRow) -> bool,
+    ) -> Result<(), Error>;
+    /// Drops a session's oldest events beyond the newest `keep`, but never one its
+    /// cursor or cancel stack still refers to.
+    fn rotate_events(&mut self, session: &str, keep: usize) -> Result<(), Error>;
+    /// Deletes events by id, and the directory paths no remaining event refers to.
+    /// Cursors and cancel entries that refer to them are left for the caller to repair.
+    fn delete_events(&mut self, ids: &[i64]) -> Result<(), Error>;
+
+    /// The interned id of a directory some event was recorded with, as its `path` or
+    /// `real_path`.
+    fn dir_id(&self, path: &str) -> Result<Option<i64>, Error>;
+
+    fn sessions(&self) -> Result<Vec<SessionRow>, Error>;
+    fn cursor(&self, session: &str) -> Result<Option<i64>, Error>;
+    /// Creates or updates the session row. `None` disarms the last move.
+    fn set_cursor(
+        &mut self,
+        session: &str,
+        cursor_id: i64,
+        last_move: Option<&LastMove>,
+        now: i64,
+    ) -> Result<(), Error>;
+    fn touch_session(&mut self, session: &str, now: i64) -> Result<(), Error>;
+    /// Sets whether `record` stores directories for `session`, creating its row if needed.
+    fn set_paused(&mut self, session: &str, paused: bool, now: i64) -> Result<(), Error>;
+    fn paused(&self, session: &str) -> Result<bool, Error>;
+    /// Links `session` to the history of `link.session`, creating its row if needed.
+    fn set_parent(&mut self, session: &str, link: &ParentLink, now: i64) -> Result<(), Error>;
+    fn parent(&self, session: &str) -> Result<Option<ParentLink>, Error>;
+
+    fn push_undo(&mut self, session: &str, from_id: i64, to_id: i64, now: i64)
+        -> Result<(), Error>;
+    fn last_undo(&self, session: &str) -> Result<Option<UndoMove>, Error>;
+    /// A session's cancel stack, oldest first.
+    fn undo_moves(&self, session: &str) -> Result<Vec<UndoMove>, Error>;
+    fn remove_undo(&mut self, id: i64) -> Result<(), Error>;
+    fn clear_undo(&mut self, session: &str) -> Result<(), Error>;
+
+    fn path_stats(&self) -> Result<Vec<PathStats>, Error>;
+
+    fn set_mark(&mut self, name: &str, path: &str, now: i64) -> Result<(), Error>;
+    fn mark(&self, name: &str) -> Result<Option<String>, Error>;
+    fn marks(&self) -> Result<Vec<Mark>, Error>;
+    /// Returns whether a mark was removed.
+    fn remove_mark(&mut self, name: &str) -> Result<bool, Error>;
+
+    /// The SQLite backend, for maintenance commands that only apply to the database file.
+    fn as_sqlite(&self) -> Option<&SqliteStore> {
+        None
+    }
+}
@@ -0,0 +1,523 @@
+//! The on-disk backend: the SQLite database under `$XDG_STATE_HOME`.
+
+use super::{
+    EventFilter, EventRow, EventScan, LastMove, NewEvent, ParentLink, PathStats, SessionRow, Store,
+    UndoMove,
+};
+use crate::db::{open_db, META_LAST_CLEANUP_KEY};
+use crate::marks::Mark;
+use crate::{Config, Error};
+use rusqlite::{named_params, params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+
+/// Events joined with their interned paths, in the column order [`event_row`] reads.
+const EVENTS_SQL: &str =
+    "SELECT events.id, dir.path, real_dir.path, events.real_dir_id, events.ts FROM events
+     JOIN dirs AS dir ON dir.id = events.dir_id
+     JOIN dirs AS real_dir ON real_dir.id = events.real_dir_id";
+
+pub struct SqliteStore {
+    conn: Connection,
+    path: PathBuf,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Ok(SqliteStore {
+            conn: open_db(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Location of the database file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The id of `path` in `dirs`, adding it if needed.
+    fn intern_dir(&self, path: &str) -> Result<i64, Error> {
+        self.conn.execute(
+            "INSERT INTO dirs (path) VALUES (?1) ON CONFLICT(path) DO NOTHING",
+            params![path],
+        )?;
+        Ok(self.conn.query_row(
+            "SELECT id FROM dirs WHERE path = ?1",
+            params![path],
+            |row| row.get(0),
+        )?)
+    }
+}
+
+impl Store for SqliteStore {
+    fn begin(&mut self) -> Result<(), Error> {
+        // IMMEDIATE takes the write lock up front, where the busy timeout applies; a
+        // deferred transaction that upgrades later can fail at once instead of waiting.
+        self.conn.execute_batch("BEGIN IMMEDIATE")?;
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), Error> {
+        self.conn.execute_batch("COMMIT")?;
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<(), Error> {
+        if !self.conn.is_autocommit() {
+            self.conn.execute_batch("ROLLBACK")?;
+        }
+        Ok(())
+    }
+
+    fn cleanup(&mut self, session: &str, now: i64, config: &Config) -> Result<(), Error> {
+        let last_cleanup_at: i64 = self
+            .conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![META_LAST_CLEANUP_KEY],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or(0);
+
+        if now.saturating_sub(last_cleanup_at) < config.cleanup_interval.value {
+            return Ok(());
+        }
+
+        let session_cutoff = now.saturating_sub(config.session_retention.value);
+        let undo_cutoff = now.saturating_sub(config.undo_retention.value);
+        let tx = self.conn.transaction()?;
+
+        tx.execute(
+            "DELETE FROM sessions WHERE last_seen_at < ?1 AND session_key != ?2",
+            params![session_cutoff, session],
+        )?;
+
+        tx.execute(
+            "DELETE FROM undo_moves WHERE created_at < ?1",
+            params![undo_cutoff],
+        )?;
+
+        // Directories whose events were all rotated away.
+        delete_unused_dirs(&tx)?;
+
+        tx.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            params![META_LAST_CLEANUP_KEY, now],
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn insert_event(&mut self, event: &NewEvent<'_>) -> Result<i64, Error> {
+        let dir_id = self.intern_dir(event.path)?;
+        let real_dir_id = self.intern_dir(event.real_path)?;
+        self.conn.execute(
+            "INSERT INTO events (session_key, dir_id, real_dir_id, ts, host) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![event.session, dir_id, real_dir_id, event.ts, event.host],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    fn event(&self, id: i64) -> Result<Option<EventRow>, Error> {
+        Ok(self
+            .conn
+            .query_row(
+                &format!("{EVENTS_SQL} WHERE events.id = ?1"),
+                params![id],
+                event_row,
+            )
+            .optional()?)
+    }
+
+    fn latest_event(&self, session: &str) -> Result<Option<EventRow>, Error> {
+        Ok(self
+            .conn
+            .query_row(
+                &format!(
+                    "{EVENTS_SQL} WHERE events.session_key = ?1 ORDER BY events.id DESC LIMIT 1"
+                ),
+                params![session],
+                event_row,
+            )
+            .optional()?)
+    }
+
+    fn scan_events(
+        &self,
+        scan: &EventScan<'_>,
+        visit: &mut dyn FnMut(EventRow) -> bool,
+    ) -> Result<(), Error> {
+        let (filter, key) = match scan.filter {
+            EventFilter::Session(session) => ("events.session_key = :key AND ", Some(session)),
+            EventFilter::Host(host) => ("events.host = :key AND ", Some(host)),
+            EventFilter::All => ("", None),
+        };
+        let (op, order) = if scan.backward {
+            ("<", "DESC")
+        } else {
+            (">", "ASC")
+        };
+        let max_ts = scan.max_ts.unwrap_or(i64::MAX);
+        let mut stmt = self.conn.prepare(&format!(
+            "{EVENTS_SQL}
+             WHERE {filter}events.id {op} :from AND events.ts <= :max_ts ORDER BY events.id {order}"
+        ))?;
+        let mut rows = match key {
+            Some(key) => stmt.query(named_params! {
+                ":key": key,
+                ":from": scan.from_id,
+                ":max_ts": max_ts,
+            }),
+            None => stmt.query(named_params! {":from": scan.from_id, ":max_ts": max_ts}),
+        }?;
+        while let Some(row) = rows.next()? {
+            if !visit(event_row(row)?) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn rotate_events(&mut self, session: &str, keep: usize) -> Result<(), Error> {
+        let min_session_cursor_id: Option<i64> = self.conn.query_row(
+            "SELECT MIN(val) FROM (
+                   SELECT cursor_id AS val FROM sessions WHERE session_key = ?1 AND cursor_id != 0
+                   UNION ALL
+                   SELECT last_bd_from_id FROM sessions WHERE session_key = ?1 AND last_bd_from_id != 0
+                   UNION ALL
+                   SELECT last_bd_to_id FROM sessions WHERE session_key = ?1 AND last_bd_to_id != 0
+                   UNION ALL
+                   SELECT from_id FROM undo_moves WHERE session_key = ?1
+                   UNION ALL
+                   SELECT to_id FROM undo_moves WHERE session_key = ?1
+                 )",
+            params![session],
+            |row| row.get(0),
+        )?;
+
+        let rotation_cutoff_id: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT id FROM events WHERE session_key = ?1 ORDER BY id DESC LIMIT 1 OFFSET ?2",
+                params![session, keep as i64 - 1],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        let (rotation_cutoff_id, min_session_cursor_id) =
+            match (rotation_cutoff_id, min_session_cursor_id) {
+                (Some(rotation_cutoff_id), Some(min_session_cursor_id)) => {
+                    (rotation_cutoff_id, min_session_cursor_id)
+                }
+                _ => return Ok(()),
+            };
+
+        let delete_before = rotation_cutoff_id.min(min_session_cursor_id);
+        if delete_before <= 0 {
+            return Ok(());
+        }
+
+        self.conn.execute(
+            "DELETE FROM events WHERE session_key = ?1 AND id < ?2",
+            params![session, delete_before],
+        )?;
+        Ok(())
+    }
+
+    fn delete_events(&mut self, ids: &[i64]) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("DELETE FROM events WHERE id = ?1")?;
+        for id in ids {
+            stmt.execute(params![id])?;
+        }
+        // Forgotten and pruned paths leave the database with their events, not at the
+        // next periodic cleanup.
+        delete_unused_dirs(&self.conn)
+    }
+
+    fn dir_id(&self, path: &str) -> Result<Option<i64>, Error> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT id FROM dirs WHERE path = ?1",
+                params![path],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn sessions(&self) -> Result<Vec<SessionRow>, Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT session_key, cursor_id, last_bd_from_id, last_bd_to_id, last_seen_at, paused
+             FROM sessions ORDER BY session_key",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(SessionRow {
+                session: row.get(0)?,
+                cursor_id: row.get(1)?,
+                last_from_id: row.get(2)?,
+                last_to_id: row.get(3)?,
+                last_seen_at: row.get(4)?,
+                paused: row.get(5)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn cursor(&self, session: &str) -> Result<Option<i64>, Error> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT cursor_id FROM sessions WHERE session_key = ?1",
+                params![session],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn set_cursor(
+        &mut self,
+        session: &str,
+        cursor_id: i64,
+        last_move: Option<&LastMove>,
+        now: i64,
+    ) -> Result<(), Error> {
+        let (delta, from_id, to_id, armed) = match last_move {
+            Some(last) => (last.delta, last.from_id, last.to_id, 1),
+            None => (0, 0, 0, 0),
+        };
+        self.conn.execute(
+            "INSERT INTO sessions (session_key, cursor_id, last_bd_delta, last_bd_from_id, last_bd_to_id, last_bd_armed, last_seen_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT(session_key) DO UPDATE SET
+               cursor_id = excluded.cursor_id,
+               last_bd_delta = excluded.last_bd_delta,
+               last_bd_from_id = excluded.last_bd_from_id,
+               last_bd_to_id = excluded.last_bd_to_id,
+               last_bd_armed = excluded.last_bd_armed,
+               last_seen_at = excluded.last_seen_at",
+            params![session, cursor_id, delta, from_id, to_id, armed, now],
+        )?;
+        Ok(())
+    }
+
+    fn touch_session(&mut self, session: &str, now: i64) -> Result<(), Error> {
+        self.conn.execute(
+            "UPDATE sessions SET last_seen_at = ?1 WHERE session_key = ?2",
+            params![now, session],
+        )?;
+        Ok(())
+    }
+
+    fn set_paused(&mut self, session: &str, paused: bool, now: i64) -> Result<(), Error> {
+        self.conn.execute(
+            "INSERT INTO sessions (session_key, cursor_id, paused, last_seen_at) VALUES (?1, 0, ?2, ?3)
+             ON CONFLICT(session_key) DO UPDATE SET
+               paused = excluded.paused,
+               last_seen_at = excluded.last_seen_at",
+            params![session, paused, now],
+        )?;
+        Ok(())
+    }
+
+    fn paused(&self, session: &str) -> Result<bool, Error> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT paused FROM sessions WHERE session_key = ?1",
+                params![session],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or(false))
+    }
+
+    fn set_parent(&mut self, session: &str, link: &ParentLink, now: i64) -> Result<(), Error> {
+        self.conn.execute(
+            "INSERT INTO sessions (session_key, cursor_id, parent_session, parent_min_id, parent_max_id, last_seen_at)
+             VALUES (?1, 0, ?2, ?3, ?4, ?5)
+             ON CONFLICT(session_key) DO UPDATE SET
+               parent_session = excluded.parent_session,
+               parent_min_id = excluded.parent_min_id,
+               parent_max_id = excluded.parent_max_id,
+               last_seen_at = excluded.last_seen_at",
+            params![session, link.session, link.min_id, link.max_id, now],
+        )?;
+        Ok(())
+    }
+
+    fn parent(&self, session: &str) -> Result<Option<ParentLink>, Error> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT parent_session, parent_min_id, parent_max_id FROM sessions
+                 WHERE session_key = ?1 AND parent_session != ''",
+                params![session],
+                |row| {
+                    Ok(ParentLink {
+                        session: row.get(0)?,
+                        min_id: row.get(1)?,
+                        max_id: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    fn push_undo(
+        &mut self,
+        session: &str,
+        from_id: i64,
+        to_id: i64,
+        now: i64,
+    ) -> Result<(), Error> {
+        self.conn.execute(
+            "INSERT INTO undo_moves (session_key, from_id, to_id, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![session, from_id, to_id, now],
+        )?;
+        Ok(())
+    }
+
+    fn last_undo(&self, session: &str) -> Result<Option<UndoMove>, Error> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT id, from_id, to_id FROM undo_moves WHERE session_key = ?1 ORDER BY id DESC LIMIT 1",
+                params![session],
+                |row| {
+                    Ok(UndoMove {
+                        id: row.get(0)?,
+                        from_id: row.get(1)?,
+                        to_id: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    fn undo_moves(&self, session: &str) -> Result<Vec<UndoMove>, Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, from_id, to_id FROM undo_moves WHERE session_key = ?1 ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![session], |row| {
+            Ok(UndoMove {
+                id: row.get(0)?,
+                from_id: row.get(1)?,
+                to_id: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn remove_undo(&mut self, id: i64) -> Result<(), Error> {
+        self.conn
+            .execute("DELETE FROM undo_moves WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    fn clear_undo(&mut self, session: &str) -> Result<(), Error> {
+        self.conn.execute(
+            "DELETE FROM undo_moves WHERE session_key = ?1",
+            params![session],
+        )?;
+        Ok(())
+    }
+
+    fn path_stats(&self) -> Result<Vec<PathStats>, Error> {
+        let mut stmt = self
+            .conn
+            // SQLite takes the bare `path` from the row holding MAX(ts), so each directory is
+            // shown as it was most recently recorded.
+            .prepare(
+                "SELECT dirs.path, COUNT(*), MAX(events.ts) FROM events
+                 JOIN dirs ON dirs.id = events.dir_id
+                 GROUP BY events.real_dir_id",
+            )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(PathStats {
+                path: row.get(0)?,
+                visits: row.get(1)?,
+                last_ts: row.get(2)?,
+            })
+        })?;
+        let mut stats = Vec::new();
+        for row in rows {
+            stats.push(row?);
+        }
+        Ok(stats)
+    }
+
+    fn set_mark(&mut self, name: &str, path: &str, now: i64) -> Result<(), Error> {
+        self.conn.execute(
+            "INSERT INTO marks (name, path, created_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(name) DO UPDATE SET path = excluded.path, created_at = excluded.created_at",
+            params![name, path, now],
+        )?;
+        Ok(())
+    }
+
+    fn mark(&self, name: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT path FROM marks WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn marks(&self) -> Result<Vec<Mark>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, path FROM marks ORDER BY name")?;
+        let rows = stmt.query_map([], |row| {
+            Ok(Mark {
+                name: row.get(0)?,
+                path: row.get(1)?,
+            })
+        })?;
+        let mut marks = Vec::new();
+        for row in rows {
+            marks.push(row?);
+        }
+        Ok(marks)
+    }
+
+    fn remove_mark(&mut self, name: &str) -> Result<bool, Error> {
+        let removed = self
+            .conn
+            .execute("DELETE FROM marks WHERE name = ?1", params![name])?;
+        Ok(removed > 0)
+    }
+
+    fn as_sqlite(&self) -> Option<&SqliteStore> {
+        Some(self)
+    }
+}
+
+/// Deletes `dirs` rows that no event refers to any more.
+fn delete_unused_dirs(conn: &Connection) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM dirs
+         WHERE id NOT IN (SELECT dir_id FROM events)
+           AND id NOT IN (SELECT real_dir_id FROM events)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn event_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<EventRow> {
+    Ok(EventRow {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        real_path: row.get(2)?,
+        real_dir_id: row.get(3)?,
+        ts: row.get(4)?,
+    })
+}
@@ -0,0 +1,193 @@
+//! End-to-end tests that run `bd-core` against a scratch state directory.
+
+use std::path::PathBuf;
+use std::process::{self, Command, Output};
+use std::{env, fs};
+
+struct Sandbox {
+    root: PathBuf,
+}
+
+impl Sandbox {
+    fn new(name: &str) -> Self {
+        let root = env::temp_dir().join(format!("bd-cli-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        Sandbox { root }
+    }
+
+    /// Creates `dirs/<rel>` and returns its path.
+    fn dir(&self, rel: &str) -> String {
+        let path = self.root.join("dirs").join(rel);
+        fs::create_dir_all(&path).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_bd-core"));
+        command
+            .args(args)
+            .env("HOME", &self.root)
+            .env("XDG_STATE_HOME", self.root.join("state"))
+            .env("XDG_CONFIG_HOME", self.root.join("config"))
+            .env_remove("BD_DB_PATH");
+        command
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    /// Runs a command that must succeed and returns its trimmed stdout.
+    fn ok(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "{args:?}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .trim_end()
+            .to_string()
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+#[test]
+fn up_jumps_to_an_ancestor_and_can_be_cancelled() {
+    let sandbox = Sandbox::new("up");
+    let proj = sandbox.dir("proj");
+    let src = sandbox.dir("proj/src");
+    let lib = sandbox.dir("proj/src/lib");
+    sandbox.ok(&["record", "--session", "s", "--pwd", &lib]);
+
+    assert_eq!(
+        sandbox.ok(&["up", "--session", "s", "--pwd", &lib, "2"]),
+        proj
+    );
+    assert_eq!(sandbox.ok(&["cancel", "--session", "s"]), lib);
+    assert_eq!(
+        sandbox.ok(&["up", "--session", "s", "--pwd", &lib, "src"]),
+        src
+    );
+    // The cancelled jump stays in history like any other visit.
+    assert_eq!(sandbox.ok(&["back", "--session", "s", "--n", "1"]), proj);
+    assert_eq!(sandbox.ok(&["back", "--session", "s", "--n", "1"]), lib);
+}
+
+#[test]
+fn unique_counts_each_directory_once() {
+    let sandbox = Sandbox::new("unique");
+    for name in ["a", "b", "a", "b", "c"] {
+        let dir = sandbox.dir(name);
+        sandbox.ok(&["record", "--session", "s", "--pwd", &dir]);
+    }
+
+    assert_eq!(
+        sandbox.ok(&["list", "--session", "s", "--unique"]),
+        "[2] ~/dirs/a\n[1] ~/dirs/b"
+    );
+    assert_eq!(
+        sandbox.ok(&["back", "--session", "s", "--n", "2", "--unique"]),
+        sandbox.dir("a")
+    );
+}
+
+#[test]
+fn a_symlinked_directory_is_one_directory() {
+    let sandbox = Sandbox::new("symlink");
+    let a = sandbox.dir("a");
+    let real = sandbox.dir("real");
+    let link = format!("{a}/../link");
+    std::os::unix::fs::symlink(&real, sandbox.root.join("dirs/link")).unwrap();
+
+    sandbox.ok(&["record", "--session", "s", "--pwd", &a]);
+    sandbox.ok(&["record", "--session", "s", "--pwd", &link]);
+    sandbox.ok(&["record", "--session", "s", "--pwd", &real]);
+    assert_eq!(sandbox.ok(&["back", "--session", "s", "--n", "1"]), a);
+    assert_eq!(
+        sandbox.ok(&["forward", "--session", "s", "--n", "1"]),
+        sandbox.root.join("dirs/link").to_string_lossy()
+    );
+
+    let physical = ["--path-policy", "physical"];
+    sandbox.ok(&[&["record", "--session", "p", "--pwd", &link][..], &physical].concat());
+    sandbox.ok(&[&["record", "--session", "p", "--pwd", &a][..], &physical].concat());
+    assert_eq!(sandbox.ok(&["back", "--session", "p", "--n", "1"]), real);
+}
+
+#[test]
+fn failures_exit_with_their_documented_codes() {
+    let sandbox = Sandbox::new("exit-codes");
+    let code = |args: &[&str]| sandbox.run(args).status.code();
+
+    assert_eq!(code(&["back", "--session", "s", "--n", "0"]), Some(2));
+    assert_eq!(code(&["back", "--session", "s", "--n", "1"]), Some(3));
+    assert_eq!(code(&["cancel", "--session", "s"]), Some(4));
+    let gone = sandbox.root.join("gone").to_string_lossy().into_owned();
+    assert_eq!(code(&["record", "--session", "s", "--pwd", &gone]), Some(5));
+}
+
+#[test]
+fn db_and_bd_db_path_select_another_database() {
+    let sandbox = Sandbox::new("db-path");
+    let db = sandbox.root.join("other.sqlite3");
+    let db_arg = db.to_string_lossy().into_owned();
+    for name in ["a", "b"] {
+        let dir = sandbox.dir(name);
+        sandbox.ok(&["--db", &db_arg, "record", "--session", "s", "--pwd", &dir]);
+    }
+
+    let output = sandbox
+        .command(&["back", "--session", "s", "--n", "1"])
+        .env("BD_DB_PATH", &db)
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim_end(),
+        sandbox.dir("a")
+    );
+    // The default database never saw those records.
+    let default = sandbox.run(&["back", "--session", "s", "--n", "1"]);
+    assert_eq!(default.status.code(), Some(3));
+}
+
+#[test]
+fn ephemeral_sessions_never_create_the_database() {
+    let sandbox = Sandbox::new("ephemeral");
+    let tmp = sandbox.root.join("tmp");
+    fs::create_dir_all(&tmp).unwrap();
+    let run = |args: &[&str]| {
+        sandbox
+            .command(args)
+            .env("BD_EPHEMERAL", "1")
+            .env_remove("XDG_RUNTIME_DIR")
+            .env("TMPDIR", &tmp)
+            .output()
+            .unwrap()
+    };
+    for name in ["a", "b"] {
+        let dir = sandbox.dir(name);
+        assert!(run(&["record", "--session", "s", "--pwd", &dir])
+            .status
+            .success());
+    }
+
+    // Without a runtime directory the snapshot goes to the temp directory.
+    let back = run(&["back", "--session", "s", "--n", "1"]);
+    assert_eq!(
+        String::from_utf8(back.stdout).unwrap().trim_end(),
+        sandbox.dir("a")
+    );
+    // Commands that need the database refuse the flag instead of creating one.
+    for args in [&["query", "a"][..], &["mark", "list"], &["doctor"]] {
+        assert_eq!(run(args).status.code(), Some(2), "{args:?}");
+    }
+    assert!(!sandbox.root.join("state").exists());
+}